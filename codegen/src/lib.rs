@@ -0,0 +1,411 @@
+// Copyright 2017 Google LLC, Matthew Vilim
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//      http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Procedural macros for declaring rivet handlers, along the lines of Rocket's codegen.
+//!
+//! ```ignore
+//! #[route(GET, "/users/<id>")]
+//! fn user(id: u64, query: Dep<dyn UrlParams>) -> String { ... }
+//!
+//! let responder = Routes::new(deps, routes![user]);
+//! ```
+//!
+//! Parameters named after a `<param>` (or trailing `<param..>`) segment of the path are parsed
+//! from the request path with `FromStr` (a 400 if that fails), or borrowed as-is if they're
+//! `&str`s; the rest must be `inject::Dep<dyn BindingTrait>`s, which
+//! are injected from the responder's binder through `inject::Injectable`, as with `inject!`. The
+//! handler's return value is converted with `IntoResponse`.
+//!
+//! The generated code refers to rivet's `inject`, `responders::routes` and `responders::Response`
+//! by their absolute paths, so these macros can only be used inside the rivet crate.
+
+extern crate proc_macro;
+extern crate proc_macro2;
+#[macro_use] extern crate quote;
+extern crate syn;
+
+use proc_macro::TokenStream;
+use proc_macro2::{Span, TokenStream as TokenStream2};
+use syn::parse::{Parse, ParseStream, Parser};
+use syn::punctuated::Punctuated;
+use syn::spanned::Spanned;
+use syn::{FnArg, Ident, ItemFn, LitStr, Pat, Path, Token, Type};
+
+/// The methods `#[route]` accepts, and the `tiny_http::Method` variant for each.
+const METHODS: &[(&str, &str)] = &[
+    ("GET", "Get"), ("HEAD", "Head"), ("POST", "Post"), ("PUT", "Put"), ("DELETE", "Delete"),
+    ("CONNECT", "Connect"), ("OPTIONS", "Options"), ("TRACE", "Trace"), ("PATCH", "Patch"),
+];
+
+/// Declares a handler for requests with one of the given methods and a path matching the
+/// template, e.g. `#[route(GET, POST, "/users/<id>")]`. The function itself is left as-is, and
+/// `routes![]` collects the generated routing entry.
+#[proc_macro_attribute]
+pub fn route(args: TokenStream, item: TokenStream) -> TokenStream {
+    let func = match syn::parse::<ItemFn>(item.clone()) {
+        Ok(func) => func,
+        Err(e) => return compile_error(e, item.into()).into(),
+    };
+    syn::parse::<RouteArgs>(args).and_then(|args| expand_route(&args, &func))
+        .unwrap_or_else(|e| compile_error(e, quote!(#func)))
+        .into()
+}
+
+/// Rewrites a function whose parameters are all injected, e.g.
+/// `fn both(parts: Dep<dyn PathParts>, query: Dep<dyn UrlParams>)`, into a function taking just
+/// the binder, which can be called as `both(&deps)` with any binder providing those bindings.
+#[proc_macro_attribute]
+pub fn inject(args: TokenStream, item: TokenStream) -> TokenStream {
+    let func = match syn::parse::<ItemFn>(item.clone()) {
+        Ok(func) => func,
+        Err(e) => return compile_error(e, item.into()).into(),
+    };
+    let result = if args.is_empty() {
+        expand_inject(&func)
+    } else {
+        Err(syn::Error::new(Span::call_site(), "#[inject] takes no arguments"))
+    };
+    result.unwrap_or_else(|e| compile_error(e, quote!(#func))).into()
+}
+
+/// Collects the routes declared by `#[route]` functions, e.g. `routes![index, users::show]`, into
+/// a `Vec<responders::routes::Route<_>>`. Routes are matched in the order they're listed.
+#[proc_macro]
+pub fn routes(input: TokenStream) -> TokenStream {
+    let paths = match Punctuated::<Path, Token![,]>::parse_terminated.parse(input) {
+        Ok(paths) => paths,
+        Err(e) => return compile_error(e, TokenStream2::new()).into(),
+    };
+    let routes = paths.into_iter().map(|mut path| {
+        let last = path.segments.last_mut().expect("Paths have at least one segment");
+        last.ident = route_fn(&last.ident);
+        quote!(#path())
+    });
+    quote!(vec![#(#routes),*]).into()
+}
+
+/// Reports `error` alongside `item`, so that uses of the item don't cause further errors.
+/// `syn::Error::into_compile_error()` can't be used since it expands to `::core::compile_error!`,
+/// which doesn't resolve in edition 2015 crates.
+fn compile_error(error: syn::Error, item: TokenStream2) -> TokenStream2 {
+    let errors = error.into_iter().map(|e| {
+        let message = e.to_string();
+        quote_spanned!(e.span()=> compile_error!(#message);)
+    });
+    quote!(#item #(#errors)*)
+}
+
+/// The arguments of `#[route]`: one or more methods, then the path template.
+struct RouteArgs {
+    methods: Vec<Ident>,
+    path: LitStr,
+}
+
+impl Parse for RouteArgs {
+    fn parse(input: ParseStream) -> syn::Result<RouteArgs> {
+        let mut methods = Vec::new();
+        while !input.peek(LitStr) {
+            let method: Ident = input.parse()?;
+            if !METHODS.iter().any(|&(name, _)| method == name) {
+                let names: Vec<_> = METHODS.iter().map(|&(name, _)| name).collect();
+                return Err(syn::Error::new(method.span(), format!(
+                    "Unknown method {}, expected one of {}", method, names.join(", "))));
+            }
+            methods.push(method);
+            input.parse::<Token![,]>()?;
+        }
+        if methods.is_empty() {
+            return Err(input.error("Expected at least one method, e.g. #[route(GET, \"/\")]"));
+        }
+        let path = input.parse()?;
+        input.parse::<Option<Token![,]>>()?;
+        Ok(RouteArgs { methods, path })
+    }
+}
+
+/// A segment of a route's path template.
+#[derive(Debug, PartialEq)]
+enum Segment {
+    Literal(String),
+    /// `<name>`, matching any one segment
+    Param(String),
+    /// `<name..>`, matching all the remaining segments; only allowed at the end
+    Rest(String),
+}
+
+impl Segment {
+    fn param(&self) -> Option<&str> {
+        match *self {
+            Segment::Literal(_) => None,
+            Segment::Param(ref name) | Segment::Rest(ref name) => Some(name),
+        }
+    }
+}
+
+/// Splits a template such as `/users/<id>/files/<path..>` into its segments. Like request paths,
+/// empty segments are ignored.
+fn parse_template(template: &str) -> Result<Vec<Segment>, String> {
+    if !template.starts_with('/') {
+        return Err(format!("Route path {:?} must start with /", template));
+    }
+    let mut segments = Vec::new();
+    for segment in template.split('/').filter(|s| !s.is_empty()) {
+        if let Some(Segment::Rest(ref name)) = segments.last() {
+            return Err(format!("<{}..> must be the last segment of the path", name));
+        }
+        let parsed = match segment.strip_prefix('<').and_then(|s| s.strip_suffix('>')) {
+            Some(name) => match name.strip_suffix("..") {
+                Some(name) => Segment::Rest(name.to_string()),
+                None => Segment::Param(name.to_string()),
+            },
+            None if segment.contains(['<', '>']) => return Err(format!(
+                "Invalid segment {:?}; parameters must make up a whole segment", segment)),
+            None => Segment::Literal(segment.to_string()),
+        };
+        if let Some(name) = parsed.param() {
+            if syn::parse_str::<Ident>(name).is_err() {
+                return Err(format!("Invalid parameter name {:?}", name));
+            }
+            if segments.iter().any(|s: &Segment| s.param() == Some(name)) {
+                return Err(format!("Parameter <{}> appears more than once", name));
+            }
+        }
+        segments.push(parsed);
+    }
+    Ok(segments)
+}
+
+/// The name of the function `#[route]` generates alongside the handler.
+fn route_fn(handler: &Ident) -> Ident {
+    Ident::new(&format!("__rivet_route_{}", handler), handler.span())
+}
+
+/// The name a parameter is bound to, if it's a plain identifier.
+fn param_name(pat: &Pat) -> Option<&Ident> {
+    match *pat {
+        Pat::Ident(ref ident) if ident.subpat.is_none() => Some(&ident.ident),
+        _ => None,
+    }
+}
+
+/// The binding an injected parameter of type `ty` is resolved from, e.g. `dyn UrlParams` for
+/// `Dep<dyn UrlParams>`.
+fn binding_key(ty: &Type) -> syn::Result<&Type> {
+    let invalid = || syn::Error::new(ty.span(),
+        "Injected parameters must be inject::Dep<dyn BindingTrait>, e.g. Dep<dyn UrlParams>");
+    let last = match *ty {
+        Type::Path(ref path) if path.qself.is_none() =>
+            path.path.segments.last().expect("Paths have at least one segment"),
+        _ => return Err(invalid()),
+    };
+    let args = match last.arguments {
+        syn::PathArguments::AngleBracketed(ref args) if last.ident == "Dep" => &args.args,
+        _ => return Err(invalid()),
+    };
+    let mut types = args.iter().filter_map(|arg| match *arg {
+        syn::GenericArgument::Type(ref ty) => Some(ty),
+        _ => None,
+    });
+    match (types.next(), types.next()) {
+        (Some(key), None) => Ok(key),
+        _ => Err(invalid()),
+    }
+}
+
+/// Whether a path parameter of type `ty` borrows the matched segment rather than parsing it.
+fn borrows_str(ty: &Type) -> bool {
+    match *ty {
+        Type::Reference(ref reference) if reference.mutability.is_none() =>
+            matches!(*reference.elem, Type::Path(ref path) if path.qself.is_none()
+                && path.path.is_ident("str")),
+        _ => false,
+    }
+}
+
+/// The bound a binder must satisfy to inject a parameter with the given binding.
+fn provides(key: &Type) -> TokenStream2 {
+    quote!(::inject::Provides<#key>)
+}
+
+/// Checks the handler is a plain function, and returns its parameters' patterns and types.
+fn params(func: &ItemFn) -> syn::Result<Vec<(&Pat, &Type)>> {
+    let sig = &func.sig;
+    if let Some(ref token) = sig.asyncness {
+        return Err(syn::Error::new(token.span(), "Handlers can't be async"));
+    }
+    if !sig.generics.params.is_empty() || sig.generics.where_clause.is_some() {
+        return Err(syn::Error::new(sig.generics.span(), "Handlers can't be generic"));
+    }
+    sig.inputs.iter().map(|arg| match *arg {
+        FnArg::Typed(ref typed) => Ok((&*typed.pat, &*typed.ty)),
+        FnArg::Receiver(ref receiver) =>
+            Err(syn::Error::new(receiver.span(), "Handlers can't take self")),
+    }).collect()
+}
+
+fn expand_route(args: &RouteArgs, func: &ItemFn) -> syn::Result<TokenStream2> {
+    let template = args.path.value();
+    let segments = parse_template(&template).map_err(|e| syn::Error::new(args.path.span(), e))?;
+    let name = &func.sig.ident;
+    let params = params(func)?;
+    for name in segments.iter().filter_map(Segment::param) {
+        if !params.iter().any(|&(pat, _)| param_name(pat).is_some_and(|i| i == name)) {
+            return Err(syn::Error::new(args.path.span(),
+                format!("<{}> doesn't match any parameter of {}", name, func.sig.ident)));
+        }
+    }
+
+    let deps = Ident::new("deps", Span::mixed_site());
+    let captures = Ident::new("captures", Span::mixed_site());
+    let mut bounds = Vec::new();
+    let mut bindings = Vec::new();
+    let mut injected = Vec::new();
+    let mut arguments = Vec::new();
+    for (i, &(pat, ty)) in params.iter().enumerate() {
+        let arg = Ident::new(&format!("arg{}", i), Span::mixed_site());
+        let capture = param_name(pat)
+            .filter(|ident| segments.iter().any(|s| s.param() == Some(&ident.to_string()[..])));
+        match capture {
+            Some(ident) => {
+                let key = ident.to_string();
+                bindings.push(if borrows_str(ty) {
+                    quote!(let #arg: #ty = #captures.get(#key);)
+                } else {
+                    quote!(let #arg: #ty = #captures.parse(#key)?;)
+                });
+            },
+            None => {
+                bounds.push(provides(binding_key(ty)?));
+                injected.push(quote!(#arg: #ty));
+            },
+        }
+        arguments.push(arg);
+    }
+
+    let methods = args.methods.iter().map(|method| {
+        let variant = METHODS.iter().find(|&&(name, _)| *method == name)
+            .map(|&(_, variant)| Ident::new(variant, method.span()))
+            .expect("Methods are validated when parsed");
+        quote!(::tiny_http::Method::#variant)
+    });
+    let segments = segments.iter().map(|segment| match *segment {
+        Segment::Literal(ref s) => quote!(::responders::routes::Segment::Literal(#s)),
+        Segment::Param(ref s) => quote!(::responders::routes::Segment::Param(#s)),
+        Segment::Rest(ref s) => quote!(::responders::routes::Segment::Rest(#s)),
+    });
+    let vis = &func.vis;
+    let route = route_fn(name);
+    let deps_type = Ident::new("RivetDeps", Span::mixed_site());
+    // Named so as not to shadow the handler itself
+    let handler = Ident::new(&format!("__rivet_handle_{}", name), Span::mixed_site());
+    Ok(quote! {
+        #func
+
+        #[doc(hidden)]
+        #vis fn #route<#deps_type>() -> ::responders::routes::Route<#deps_type>
+                where #deps_type: #(#bounds +)* 'static {
+            fn #handler<#deps_type>(#deps: &#deps_type, #captures: &::responders::routes::Captures)
+                    -> ::responders::Response where #deps_type: #(#bounds +)* 'static {
+                // Path parameters are parsed within the injected closure, so a bad one is a 400
+                ::inject::Injectable::inject(&|#(#injected),*| -> ::responders::Response {
+                    #(#bindings)*
+                    ::responders::routes::IntoResponse::into_response(#name(#(#arguments),*))
+                }, #deps)
+            }
+            ::responders::routes::Route::new(
+                &[#(#methods),*], #template, &[#(#segments),*], #handler::<#deps_type>)
+        }
+    })
+}
+
+fn expand_inject(func: &ItemFn) -> syn::Result<TokenStream2> {
+    let params = params(func)?;
+    let deps = Ident::new("deps", Span::mixed_site());
+    let bounds = params.iter().map(|&(_, ty)| binding_key(ty).map(provides))
+        .collect::<syn::Result<Vec<_>>>()?;
+    let inputs = params.iter().map(|&(pat, ty)| quote!(#pat: #ty));
+
+    let ItemFn { ref attrs, ref vis, ref sig, ref block } = *func;
+    let (name, output) = (&sig.ident, &sig.output);
+    let deps_type = Ident::new("RivetDeps", Span::mixed_site());
+    Ok(quote! {
+        #(#attrs)*
+        #vis fn #name<#deps_type>(#deps: &#deps_type) #output
+                where #deps_type: #(#bounds +)* 'static {
+            ::inject::Injectable::inject(&|#(#inputs),*| #output #block, #deps)
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn templates() {
+        assert_eq!(parse_template("/"), Ok(vec![]));
+        assert_eq!(parse_template("/users/<id>/files/<path..>/"), Ok(vec![
+            Segment::Literal("users".into()), Segment::Param("id".into()),
+            Segment::Literal("files".into()), Segment::Rest("path".into())]));
+
+        assert!(parse_template("users").unwrap_err().contains("must start with /"));
+        assert!(parse_template("/<path..>/more").unwrap_err().contains("must be the last"));
+        assert!(parse_template("/user-<id>").unwrap_err().contains("whole segment"));
+        assert!(parse_template("/<1d>").unwrap_err().contains("Invalid parameter name"));
+        assert!(parse_template("/<id>/<id>").unwrap_err().contains("more than once"));
+    }
+
+    #[test]
+    fn route_args() {
+        let args: RouteArgs = syn::parse_str("GET, POST, \"/users/<id>\"").unwrap();
+        assert_eq!(args.methods, vec!["GET", "POST"]);
+        assert_eq!(args.path.value(), "/users/<id>");
+
+        assert!(syn::parse_str::<RouteArgs>("\"/\"").is_err());
+        assert!(syn::parse_str::<RouteArgs>("FETCH, \"/\"").is_err());
+    }
+
+    #[test]
+    fn injected_params() {
+        for ty in &["Dep<dyn UrlParams>", "inject::Dep<'a, dyn UrlParams>", "::inject::Dep<T>"] {
+            let key = binding_key(&syn::parse_str(ty).unwrap()).map(|key| quote!(#key).to_string());
+            assert!(key.is_ok(), "{}", ty);
+        }
+        for ty in &["u64", "&dyn UrlParams", "Dep", "Dep<'a>", "Vec<dyn UrlParams>"] {
+            assert!(binding_key(&syn::parse_str(ty).unwrap()).is_err(), "{}", ty);
+        }
+    }
+
+    #[test]
+    fn path_params() {
+        for ty in &["&str", "&'a str"] {
+            assert!(borrows_str(&syn::parse_str(ty).unwrap()), "{}", ty);
+        }
+        for ty in &["str", "String", "&mut str", "&String", "&[str]", "u64"] {
+            assert!(!borrows_str(&syn::parse_str(ty).unwrap()), "{}", ty);
+        }
+    }
+
+    #[test]
+    fn unmatched_params() {
+        let func: ItemFn = syn::parse_str("fn user(id: u64) -> String { id.to_string() }").unwrap();
+        let args: RouteArgs = syn::parse_str("GET, \"/users/<name>\"").unwrap();
+        let error = expand_route(&args, &func).unwrap_err().to_string();
+        assert!(error.contains("<name> doesn't match any parameter of user"), "{}", error);
+
+        // Without a matching segment, id would have to be injected
+        let args: RouteArgs = syn::parse_str("GET, \"/users\"").unwrap();
+        assert!(expand_route(&args, &func).is_err());
+    }
+}
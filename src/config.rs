@@ -0,0 +1,474 @@
+// Copyright 2017 Google LLC, Matthew Vilim
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//      http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Server configuration, loaded from (in increasing order of precedence) built-in defaults, a TOML
+//! config file, `RIVET_*` environment variables, and command-line flags.
+
+use logging;
+use request;
+use responders;
+use std::fs;
+use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+use std::time::Duration;
+use toml;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Config {
+    pub address: IpAddr,
+    pub port: u16,
+    pub workers: usize,
+    /// Names of the enabled responders, in `responders::REGISTRY` order
+    pub responders: Vec<String>,
+    /// How long to wait for in-flight requests to finish when shutting down
+    pub shutdown_timeout: Duration,
+    /// If set, enables the `/quit` admin endpoint, guarded by this token
+    pub admin_token: Option<String>,
+    pub log_format: logging::Format,
+    pub log_level: logging::Level,
+    /// Where to write the access log; stdout if unset
+    pub log_file: Option<String>,
+    /// Refuse to start if any responder reports problems with its setup, e.g. ambiguous routes or
+    /// missing dependencies
+    pub strict: bool,
+    /// The largest request body, in bytes, the server will accept
+    pub max_body_size: usize,
+    /// If set, serves the files in this directory at `static_prefix`
+    pub static_dir: Option<String>,
+    pub static_prefix: String,
+    /// List the contents of static directories without an `index.html`
+    pub static_listing: bool,
+}
+
+/// What the server was asked to do.
+#[derive(Debug, PartialEq)]
+pub enum Command {
+    Run(Config),
+    Help,
+}
+
+impl Default for Config {
+    fn default() -> Config {
+        Config {
+            address: IpAddr::V4(Ipv4Addr::UNSPECIFIED),
+            port: 8000,
+            workers: 4,
+            responders: responders::REGISTRY.iter().map(|r| r.name.to_string()).collect(),
+            shutdown_timeout: Duration::from_secs(10),
+            admin_token: None,
+            log_format: logging::Format::Common,
+            log_level: logging::Level::Info,
+            log_file: None,
+            strict: false,
+            max_body_size: request::DEFAULT_MAX_BODY_SIZE,
+            static_dir: None,
+            static_prefix: "/static".into(),
+            static_listing: false,
+        }
+    }
+}
+
+/// Settings from a single source; unset fields fall back to lower-precedence sources. This is
+/// also the schema of the config file, e.g.
+///
+/// ```toml
+/// address = "127.0.0.1"
+/// port = 8080
+/// responders = ["raw", "pattern"]
+/// ```
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct Overrides {
+    address: Option<IpAddr>,
+    port: Option<u16>,
+    workers: Option<usize>,
+    /// Replaces the set of enabled responders
+    responders: Option<Vec<String>>,
+    /// Responders to enable in addition to the current set
+    #[serde(default)]
+    enable: Vec<String>,
+    /// Responders to disable
+    #[serde(default)]
+    disable: Vec<String>,
+    /// In seconds
+    shutdown_timeout: Option<u64>,
+    admin_token: Option<String>,
+    log_format: Option<logging::Format>,
+    log_level: Option<logging::Level>,
+    log_file: Option<String>,
+    strict: Option<bool>,
+    /// In bytes
+    max_body_size: Option<usize>,
+    static_dir: Option<String>,
+    static_prefix: Option<String>,
+    static_listing: Option<bool>,
+}
+
+impl Config {
+    fn apply(&mut self, overrides: Overrides) {
+        if let Some(address) = overrides.address { self.address = address; }
+        if let Some(port) = overrides.port { self.port = port; }
+        if let Some(workers) = overrides.workers { self.workers = workers; }
+        if let Some(responders) = overrides.responders { self.responders = responders; }
+        if let Some(timeout) = overrides.shutdown_timeout {
+            self.shutdown_timeout = Duration::from_secs(timeout);
+        }
+        if let Some(token) = overrides.admin_token { self.admin_token = Some(token); }
+        if let Some(format) = overrides.log_format { self.log_format = format; }
+        if let Some(level) = overrides.log_level { self.log_level = level; }
+        if let Some(file) = overrides.log_file { self.log_file = Some(file); }
+        if let Some(strict) = overrides.strict { self.strict = strict; }
+        if let Some(size) = overrides.max_body_size { self.max_body_size = size; }
+        if let Some(dir) = overrides.static_dir { self.static_dir = Some(dir); }
+        if let Some(prefix) = overrides.static_prefix { self.static_prefix = prefix; }
+        if let Some(listing) = overrides.static_listing { self.static_listing = listing; }
+        self.responders.extend(overrides.enable);
+        let disable = overrides.disable;
+        self.responders.retain(|r| !disable.contains(r));
+    }
+
+    fn validate(mut self) -> Result<Config, String> {
+        if self.workers == 0 {
+            return Err("workers must be at least 1".into());
+        }
+        if self.admin_token.as_ref().is_some_and(|t| t.is_empty()) {
+            return Err("admin_token must not be empty".into());
+        }
+        let unknown = self.responders.iter().find(|r| responders::registration(r).is_none());
+        if let Some(unknown) = unknown {
+            return Err(format!("Unknown responder {:?}; available responders are: {}",
+                               unknown, responder_names().join(", ")));
+        }
+        if self.static_dir.is_some() {
+            // The static files can't share a mount point with the other responders
+            let prefix = self.static_prefix.trim_matches('/');
+            if prefix.is_empty() || (prefix == "quit" && self.admin_token.is_some())
+                    || self.responders.iter().any(|r| r == prefix) {
+                return Err(format!("static_prefix {:?} conflicts with another responder",
+                                   self.static_prefix));
+            }
+        }
+        let enabled = self.responders;
+        self.responders = responder_names().into_iter()
+            .filter(|name| enabled.iter().any(|e| e == name))
+            .map(|name| name.to_string())
+            .collect();
+        Ok(self)
+    }
+
+    /// The address to bind the server to, e.g. `0.0.0.0:8000` or `[::1]:8000`.
+    pub fn listen_addr(&self) -> SocketAddr {
+        SocketAddr::new(self.address, self.port)
+    }
+}
+
+/// Loads the configuration from the given command-line arguments (excluding the program name)
+/// and environment, which is passed as a lookup function so tests don't depend on the real one.
+pub fn load<I, E>(args: I, env: E) -> Result<Command, String>
+    where I: IntoIterator<Item=String>, E: Fn(&str) -> Option<String>
+{
+    let (flags, config_file) = match parse_args(args)? {
+        Some(parsed) => parsed,
+        None => return Ok(Command::Help),
+    };
+
+    let mut config = Config::default();
+    if let Some(path) = config_file.or_else(|| env("RIVET_CONFIG")) {
+        let contents = fs::read_to_string(&path)
+            .map_err(|e| format!("Failed to read config file {}: {}", path, e))?;
+        let overrides = parse_toml(&contents)
+            .map_err(|e| format!("Invalid config file {}: {}", path, e))?;
+        config.apply(overrides);
+    }
+    config.apply(from_env(env)?);
+    config.apply(flags);
+    config.validate().map(Command::Run)
+}
+
+fn parse_toml(contents: &str) -> Result<Overrides, String> {
+    toml::from_str(contents).map_err(|e| e.to_string())
+}
+
+fn from_env<E: Fn(&str) -> Option<String>>(env: E) -> Result<Overrides, String> {
+    Ok(Overrides {
+        address: parse_env(&env, "RIVET_ADDRESS")?,
+        port: parse_env(&env, "RIVET_PORT")?,
+        workers: parse_env(&env, "RIVET_WORKERS")?,
+        responders: env("RIVET_RESPONDERS").map(|v| split_list(&v)),
+        enable: env("RIVET_ENABLE").map(|v| split_list(&v)).unwrap_or_default(),
+        disable: env("RIVET_DISABLE").map(|v| split_list(&v)).unwrap_or_default(),
+        shutdown_timeout: parse_env(&env, "RIVET_SHUTDOWN_TIMEOUT")?,
+        admin_token: env("RIVET_ADMIN_TOKEN"),
+        log_format: parse_env(&env, "RIVET_LOG_FORMAT")?,
+        log_level: parse_env(&env, "RIVET_LOG_LEVEL")?,
+        log_file: env("RIVET_LOG_FILE"),
+        strict: parse_env(&env, "RIVET_STRICT")?,
+        max_body_size: parse_env(&env, "RIVET_MAX_BODY_SIZE")?,
+        static_dir: env("RIVET_STATIC_DIR"),
+        static_prefix: env("RIVET_STATIC_PREFIX"),
+        static_listing: parse_env(&env, "RIVET_STATIC_LISTING")?,
+    })
+}
+
+fn parse_env<T, E>(env: &E, name: &str) -> Result<Option<T>, String>
+    where T: ::std::str::FromStr, E: Fn(&str) -> Option<String>
+{
+    env(name).map(|v| parse_value(name, &v)).transpose()
+}
+
+/// Parses command-line flags, returning `None` if help was requested. Flag values can be passed
+/// either as `--flag value` or `--flag=value`. The config file path is returned separately since
+/// it determines the precedence of the other settings.
+fn parse_args<I>(args: I) -> Result<Option<(Overrides, Option<String>)>, String>
+    where I: IntoIterator<Item=String>
+{
+    let mut overrides = Overrides::default();
+    let mut config_file = None;
+    let mut args = args.into_iter();
+    while let Some(arg) = args.next() {
+        if arg == "-h" || arg == "--help" {
+            return Ok(None);
+        }
+        let (flag, inline_value) = match arg.find('=') {
+            Some(index) if arg.starts_with("--") =>
+                (arg[..index].to_string(), Some(arg[index + 1..].to_string())),
+            _ => (arg.clone(), None),
+        };
+        let mut value = || inline_value.clone().or_else(|| args.next())
+            .ok_or_else(|| format!("Missing value for {}", flag));
+        match flag.as_ref() {
+            "--config" => config_file = Some(value()?),
+            "--address" => overrides.address = Some(parse_value(&flag, &value()?)?),
+            "--port" => overrides.port = Some(parse_value(&flag, &value()?)?),
+            "--workers" => overrides.workers = Some(parse_value(&flag, &value()?)?),
+            "--responders" => overrides.responders = Some(split_list(&value()?)),
+            "--enable" => overrides.enable.push(value()?),
+            "--disable" => overrides.disable.push(value()?),
+            "--shutdown-timeout" =>
+                overrides.shutdown_timeout = Some(parse_value(&flag, &value()?)?),
+            "--admin-token" => overrides.admin_token = Some(value()?),
+            "--log-format" => overrides.log_format = Some(parse_value(&flag, &value()?)?),
+            "--log-level" => overrides.log_level = Some(parse_value(&flag, &value()?)?),
+            "--log-file" => overrides.log_file = Some(value()?),
+            // A switch, but --strict=false can override the environment or config file
+            "--strict" => overrides.strict = Some(match inline_value {
+                Some(ref v) => parse_value(&flag, v)?,
+                None => true,
+            }),
+            "--max-body-size" => overrides.max_body_size = Some(parse_value(&flag, &value()?)?),
+            "--static-dir" => overrides.static_dir = Some(value()?),
+            "--static-prefix" => overrides.static_prefix = Some(value()?),
+            "--static-listing" => overrides.static_listing = Some(match inline_value {
+                Some(ref v) => parse_value(&flag, v)?,
+                None => true,
+            }),
+            _ => return Err(format!("Unknown argument {:?}", arg)),
+        }
+    }
+    Ok(Some((overrides, config_file)))
+}
+
+fn parse_value<T: ::std::str::FromStr>(name: &str, value: &str) -> Result<T, String> {
+    value.parse().map_err(|_| format!("Invalid value for {}: {:?}", name, value))
+}
+
+fn split_list(value: &str) -> Vec<String> {
+    value.split(',').map(str::trim).filter(|s| !s.is_empty()).map(String::from).collect()
+}
+
+fn responder_names() -> Vec<&'static str> {
+    responders::REGISTRY.iter().map(|r| r.name).collect()
+}
+
+/// The `--help` text, including the list of available responders.
+pub fn usage() -> String {
+    let defaults = Config::default();
+    let mut usage = format!("\
+Usage: rivet [OPTIONS]
+
+Options:
+  --config FILE        Load settings from a TOML file [env: RIVET_CONFIG]
+  --address ADDRESS    Address to listen on (default {}) [env: RIVET_ADDRESS]
+  --port PORT          Port to listen on (default {}) [env: RIVET_PORT]
+  --workers N          Number of worker threads (default {}) [env: RIVET_WORKERS]
+  --responders A,B,..  Enable only the given responders (default all) [env: RIVET_RESPONDERS]
+  --enable NAME        Enable a responder, may be repeated [env: RIVET_ENABLE]
+  --disable NAME       Disable a responder, may be repeated [env: RIVET_DISABLE]
+  --shutdown-timeout S Seconds to wait for in-flight requests on shutdown (default {})
+                       [env: RIVET_SHUTDOWN_TIMEOUT]
+  --admin-token TOKEN  Enable the /quit endpoint, which requires this token as a ?token= param
+                       or Authorization: Bearer header (default disabled) [env: RIVET_ADMIN_TOKEN]
+  --log-format FORMAT  Access log format, common or json (default common) [env: RIVET_LOG_FORMAT]
+  --log-level LEVEL    One of off, error, info, or debug (default {}) [env: RIVET_LOG_LEVEL]
+  --log-file FILE      Append the access log to FILE instead of stdout [env: RIVET_LOG_FILE]
+  --strict             Refuse to start if any responder reports a problem with its setup, such as
+                       ambiguous routes or missing dependencies [env: RIVET_STRICT]
+  --max-body-size BYTES
+                       Reject request bodies larger than this with a 413 (default {})
+                       [env: RIVET_MAX_BODY_SIZE]
+  --static-dir DIR     Serve the files in DIR (default disabled) [env: RIVET_STATIC_DIR]
+  --static-prefix PATH Where to serve --static-dir (default {}) [env: RIVET_STATIC_PREFIX]
+  --static-listing     List the contents of static directories without an index.html
+                       [env: RIVET_STATIC_LISTING]
+  -h, --help           Print this message
+
+Settings are applied in order from the config file, environment variables, and flags, so later
+sources take precedence.
+
+Responders:
+", defaults.address, defaults.port, defaults.workers, defaults.shutdown_timeout.as_secs(),
+            defaults.log_level, defaults.max_body_size, defaults.static_prefix);
+    for registration in responders::REGISTRY {
+        usage.push_str(&format!("  {:<20} {}\n", registration.name, registration.description));
+    }
+    usage
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+
+    fn load_with(args: &[&str], env: &[(&str, &str)]) -> Result<Command, String> {
+        let env: HashMap<String, String> =
+            env.iter().map(|&(k, v)| (k.to_string(), v.to_string())).collect();
+        load(args.iter().map(|s| s.to_string()), |k| env.get(k).cloned())
+    }
+
+    fn config(args: &[&str], env: &[(&str, &str)]) -> Config {
+        match load_with(args, env).unwrap() {
+            Command::Run(config) => config,
+            Command::Help => panic!("Unexpected help"),
+        }
+    }
+
+    #[test]
+    fn defaults() {
+        assert_eq!(config(&[], &[]), Config::default());
+        assert_eq!(Config::default().listen_addr().to_string(), "0.0.0.0:8000");
+    }
+
+    #[test]
+    fn flags() {
+        let config = config(&["--address", "127.0.0.1", "--port=8080", "--workers", "2"], &[]);
+        assert_eq!(config.listen_addr().to_string(), "127.0.0.1:8080");
+        assert_eq!(config.workers, 2);
+    }
+
+    #[test]
+    fn ipv6_address() {
+        let config = config(&["--address", "::1"], &[]);
+        assert_eq!(config.listen_addr().to_string(), "[::1]:8000");
+        assert!(load_with(&["--address", "[::1]"], &[]).unwrap_err().contains("--address"));
+        assert!(load_with(&[], &[("RIVET_ADDRESS", "localhost")]).is_err());
+    }
+
+    #[test]
+    fn shutdown() {
+        let config = config(&["--shutdown-timeout", "3"], &[("RIVET_ADMIN_TOKEN", "secret")]);
+        assert_eq!(config.shutdown_timeout, Duration::from_secs(3));
+        assert_eq!(config.admin_token, Some("secret".into()));
+        assert!(load_with(&["--admin-token="], &[]).is_err());
+    }
+
+    #[test]
+    fn logging() {
+        let config = config(&["--log-level=debug"], &[("RIVET_LOG_FORMAT", "json")]);
+        assert_eq!(config.log_level, logging::Level::Debug);
+        assert_eq!(config.log_format, logging::Format::Json);
+        assert!(load_with(&["--log-format", "xml"], &[]).is_err());
+
+        let mut config = Config::default();
+        config.apply(parse_toml("log_level = \"error\"\nlog_file = \"access.log\"").unwrap());
+        assert_eq!(config.log_level, logging::Level::Error);
+        assert_eq!(config.log_file, Some("access.log".into()));
+    }
+
+    #[test]
+    fn strict() {
+        assert!(!Config::default().strict);
+        assert!(config(&["--strict", "--port", "1"], &[]).strict);
+        assert!(config(&[], &[("RIVET_STRICT", "true")]).strict);
+        let env = [("RIVET_STRICT", "true")];
+        assert!(!config(&["--strict=false"], &env).strict);
+        assert!(load_with(&["--strict=yes"], &[]).is_err());
+    }
+
+    #[test]
+    fn static_files() {
+        assert_eq!(Config::default().static_dir, None);
+        let enabled = config(&["--static-dir", "public", "--static-listing"], &[]);
+        assert_eq!(enabled.static_dir, Some("public".to_string()));
+        assert_eq!(enabled.static_prefix, "/static");
+        assert!(enabled.static_listing);
+        let env = [("RIVET_STATIC_DIR", "public"), ("RIVET_STATIC_PREFIX", "/files")];
+        assert_eq!(config(&[], &env).static_prefix, "/files");
+
+        assert!(load_with(&["--static-dir", "public", "--static-prefix", "/raw/"], &[]).is_err());
+        assert!(load_with(&["--static-dir", "public", "--static-prefix", "/"], &[]).is_err());
+        // Only checked if static files are enabled
+        assert!(load_with(&["--static-prefix", "/"], &[]).is_ok());
+    }
+
+    #[test]
+    fn max_body_size() {
+        assert_eq!(Config::default().max_body_size, request::DEFAULT_MAX_BODY_SIZE);
+        assert_eq!(config(&["--max-body-size", "10"], &[]).max_body_size, 10);
+        assert_eq!(config(&[], &[("RIVET_MAX_BODY_SIZE", "20")]).max_body_size, 20);
+        assert!(load_with(&["--max-body-size=-1"], &[]).is_err());
+    }
+
+    #[test]
+    fn flags_override_env() {
+        let config = config(&["--port", "9000"], &[("RIVET_PORT", "8080"), ("RIVET_WORKERS", "3")]);
+        assert_eq!(config.port, 9000);
+        assert_eq!(config.workers, 3);
+    }
+
+    #[test]
+    fn responders() {
+        assert_eq!(config(&["--responders", "traits,raw"], &[]).responders, vec!["raw", "traits"]);
+        assert_eq!(config(&["--enable", "pattern"], &[("RIVET_RESPONDERS", "raw")]).responders,
+                   vec!["raw", "pattern"]);
+        let disabled = config(&["--disable", "raw", "--disable", "factory"], &[]).responders;
+        assert_eq!(disabled,
+                   vec!["stringly", "pattern", "closure", "traits", "traits_macro", "codegen"]);
+    }
+
+    #[test]
+    fn toml() {
+        let mut config = Config::default();
+        config.apply(parse_toml("address = \"::1\"\nport = 1234\ndisable = [\"raw\"]").unwrap());
+        assert_eq!(config.port, 1234);
+        assert_eq!(config.listen_addr().to_string(), "[::1]:1234");
+        assert!(!config.responders.contains(&"raw".to_string()));
+
+        assert!(parse_toml("prot = 1234").is_err());
+    }
+
+    #[test]
+    fn help() {
+        assert_eq!(load_with(&["--port", "1", "--help"], &[]), Ok(Command::Help));
+        assert!(usage().contains("traits_macro"));
+    }
+
+    #[test]
+    fn errors() {
+        assert!(load_with(&["--port", "http"], &[]).unwrap_err().contains("--port"));
+        assert!(load_with(&["--port"], &[]).unwrap_err().contains("Missing value"));
+        assert!(load_with(&["--bogus"], &[]).unwrap_err().contains("Unknown argument"));
+        assert!(load_with(&["--workers", "0"], &[]).is_err());
+        let unknown = load_with(&[], &[("RIVET_ENABLE", "nope")]).unwrap_err();
+        assert!(unknown.contains("available responders"));
+    }
+}
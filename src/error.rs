@@ -0,0 +1,130 @@
+// Copyright 2017 Google LLC, Matthew Vilim
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//      http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! The errors a `Responder` can fail with, and how they map to HTTP responses.
+
+use std::error::Error;
+use std::fmt;
+use tiny_http;
+use util;
+
+/// Not every variant is produced by the bundled responders, but they're all available to new ones.
+#[allow(dead_code)]
+#[derive(Debug, Clone, PartialEq)]
+pub enum RivetError {
+    /// Nothing exists at the requested URL
+    NotFound(String),
+    /// The request was malformed, e.g. a URL parameter couldn't be parsed
+    BadRequest(String),
+    /// The request isn't permitted, e.g. a missing or invalid token
+    Forbidden(String),
+    /// The URL exists but doesn't support the request's method
+    MethodNotAllowed { allowed: Vec<tiny_http::Method> },
+    /// The request body is larger than the server accepts
+    PayloadTooLarge { limit: usize },
+    /// The request body isn't of a type the handler can decode
+    UnsupportedMediaType(String),
+    /// A dependency the handler needs wasn't bound; this is a server bug, not a client error
+    MissingBinding(String),
+    /// Any other server-side failure
+    Internal(String),
+}
+
+impl RivetError {
+    pub fn status_code(&self) -> u16 {
+        match *self {
+            RivetError::NotFound(_) => 404,
+            RivetError::BadRequest(_) => 400,
+            RivetError::Forbidden(_) => 403,
+            RivetError::MethodNotAllowed { .. } => 405,
+            RivetError::PayloadTooLarge { .. } => 413,
+            RivetError::UnsupportedMediaType(_) => 415,
+            RivetError::MissingBinding(_) | RivetError::Internal(_) => 500,
+        }
+    }
+
+    /// The response sent to the client. Server errors don't expose their details, which should
+    /// be logged instead.
+    pub fn into_response(self) -> tiny_http::ResponseBox {
+        let status = tiny_http::StatusCode::from(self.status_code());
+        let body = match self {
+            RivetError::MissingBinding(_) | RivetError::Internal(_) =>
+                status.default_reason_phrase().to_string(),
+            _ => self.to_string(),
+        };
+        let mut response = tiny_http::Response::from_string(body).with_status_code(status);
+        if let RivetError::MethodNotAllowed { ref allowed } = self {
+            response.add_header(util::allow_header(allowed));
+        }
+        response.boxed()
+    }
+}
+
+impl fmt::Display for RivetError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            RivetError::NotFound(ref msg) | RivetError::BadRequest(ref msg) |
+            RivetError::Forbidden(ref msg) | RivetError::UnsupportedMediaType(ref msg) |
+            RivetError::Internal(ref msg) => f.write_str(msg),
+            RivetError::MethodNotAllowed { ref allowed } => {
+                let allowed: Vec<_> = allowed.iter().map(|m| m.to_string()).collect();
+                write!(f, "Method not allowed; expected one of {}", allowed.join(", "))
+            },
+            RivetError::PayloadTooLarge { limit } =>
+                write!(f, "Request body is larger than {} bytes", limit),
+            RivetError::MissingBinding(ref binding) => write!(f, "No binding for {}", binding),
+        }
+    }
+}
+
+impl Error for RivetError {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use responders::testing::body;
+
+    #[test]
+    fn status_codes() {
+        assert_eq!(RivetError::NotFound("x".into()).status_code(), 404);
+        assert_eq!(RivetError::BadRequest("x".into()).status_code(), 400);
+        assert_eq!(RivetError::PayloadTooLarge { limit: 1 }.status_code(), 413);
+        assert_eq!(RivetError::UnsupportedMediaType("x".into()).status_code(), 415);
+        assert_eq!(RivetError::MissingBinding("x".into()).status_code(), 500);
+    }
+
+    #[test]
+    fn client_error_response() {
+        let response = RivetError::NotFound("No such page".into()).into_response();
+        assert_eq!(response.status_code().0, 404);
+        assert_eq!(body(response), "No such page");
+    }
+
+    #[test]
+    fn server_error_response() {
+        let response = RivetError::Internal("database password is hunter2".into()).into_response();
+        assert_eq!(response.status_code().0, 500);
+        assert_eq!(body(response), "Internal Server Error");
+    }
+
+    #[test]
+    fn method_not_allowed() {
+        let error = RivetError::MethodNotAllowed {
+            allowed: vec![tiny_http::Method::Get, tiny_http::Method::Post] };
+        let response = error.into_response();
+        assert_eq!(response.status_code().0, 405);
+        let allow = response.headers().iter().find(|h| h.field.equiv("Allow")).unwrap();
+        assert_eq!(allow.value.as_str(), "GET, POST");
+    }
+}
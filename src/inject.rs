@@ -0,0 +1,120 @@
+// Copyright 2017 Google LLC, Matthew Vilim
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//      http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Injects a binder's bindings into functions of any arity (up to 12 parameters), as used by the
+//! `inject!` macros. Each parameter is a `Dep<dyn BindingTrait>`, which is resolved from the binder
+//! through that binding's trait, e.g.
+//!
+//! ```ignore
+//! fn both(parts: Dep<dyn PathParts>, query: Dep<dyn UrlParams>) -> String { ... }
+//!
+//! let response = Injectable::inject(&both, &deps);
+//! ```
+//!
+//! A function whose parameters aren't all bound by the binder doesn't implement `Injectable`,
+//! so injecting it fails to compile.
+
+use std::fmt;
+use std::ops::Deref;
+
+/// Implemented by `binding!` and `provider!` for their trait objects (e.g. `dyn PathParts`), which
+/// serve as the keys of bindings at the type level.
+pub trait Binding {
+    type Value: ?Sized;
+}
+
+/// A binder that can resolve the binding `K`; implemented by `binding!` and `provider!`.
+pub trait Provides<K: ?Sized + Binding> {
+    fn provide(&self) -> &K::Value;
+}
+
+/// An injected parameter, which dereferences to the value bound to `K`.
+pub struct Dep<'a, K: ?Sized + Binding>(&'a K::Value);
+
+impl<'a, K: ?Sized + Binding> Deref for Dep<'a, K> {
+    type Target = K::Value;
+
+    fn deref(&self) -> &K::Value { self.0 }
+}
+
+impl<'a, K: ?Sized + Binding> fmt::Debug for Dep<'a, K> where K::Value: fmt::Debug {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result { self.0.fmt(f) }
+}
+
+/// A function that can be called with dependencies from the binder `D`. `Args` describes the
+/// function's parameters; it only serves to keep the implementations for each arity apart, and is
+/// always inferred.
+pub trait Injectable<D, Args> {
+    type Output;
+
+    fn inject(&self, deps: &D) -> Self::Output;
+}
+
+macro_rules! injectable {
+    ($($arg:ident),*) => {
+        impl<Deps, Func, Out, $($arg: ?Sized + Binding),*> Injectable<Deps, fn($(&$arg),*) -> Out>
+            for Func where Func: Fn($(Dep<$arg>),*) -> Out, $(Deps: Provides<$arg>),*
+        {
+            type Output = Out;
+
+            #[allow(unused_variables)]
+            fn inject(&self, deps: &Deps) -> Out {
+                self($(Dep(Provides::<$arg>::provide(deps))),*)
+            }
+        }
+    }
+}
+
+for_each_arity!(injectable);
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    binder!(Deps);
+    binding!(Deps, Name, String);
+    binding!(Deps, Count, u32);
+    provider!(Deps, Initial, str, Name, |d: &'a dyn Name| &d.get()[..1]);
+
+    fn deps() -> Deps {
+        let mut deps = Deps::new();
+        bind!(deps, Name, "Rivet".to_string());
+        bind!(deps, Count, 3);
+        deps
+    }
+
+    fn none() -> &'static str { "none" }
+
+    fn repeat(name: Dep<dyn Name>, count: Dep<dyn Count>) -> String {
+        name.repeat(*count as usize)
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    fn twelve(a: Dep<dyn Count>, b: Dep<dyn Count>, c: Dep<dyn Count>, d: Dep<dyn Count>,
+              e: Dep<dyn Count>, f: Dep<dyn Count>, g: Dep<dyn Count>, h: Dep<dyn Count>,
+              i: Dep<dyn Count>, j: Dep<dyn Count>, k: Dep<dyn Count>, l: Dep<dyn Initial>)
+            -> String {
+        let sum: u32 = [a, b, c, d, e, f, g, h, i, j, k].iter().map(|n| **n).sum();
+        format!("{}{}", &*l, sum)
+    }
+
+    #[test]
+    fn any_arity() {
+        let deps = deps();
+        assert_eq!(Injectable::inject(&none, &deps), "none");
+        assert_eq!(Injectable::inject(&repeat, &deps), "RivetRivetRivet");
+        assert_eq!(Injectable::inject(&twelve, &deps), "R33");
+        assert_eq!(Injectable::inject(&|count: Dep<dyn Count>| *count + 1, &deps), 4);
+    }
+}
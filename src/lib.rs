@@ -0,0 +1,20 @@
+// Copyright 2017 Google LLC, Matthew Vilim
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//      http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! The dependency injection macros and traits the responders are built on. They're a library,
+//! rather than part of the server binary, so their documentation examples - including the ones
+//! that check a mistake fails to compile - are run as tests.
+
+#[macro_use] pub mod macros;
+pub mod inject;
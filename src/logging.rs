@@ -0,0 +1,365 @@
+// Copyright 2017 Google LLC, Matthew Vilim
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//      http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Access logging and server messages.
+//!
+//! Each handled request produces one access log entry, written to stdout or a file in either
+//! Common Log Format or as JSON lines. Server messages (startup, shutdown, errors) are written to
+//! stderr. Both are filtered by the configured `Level`.
+
+use serde_json;
+use std::fmt;
+use std::fs::OpenOptions;
+use std::io::{self, Write};
+use std::net::SocketAddr;
+use std::str::FromStr;
+use std::sync::Mutex;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tiny_http;
+use util;
+
+/// Logging verbosity, from least to most verbose.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Level {
+    /// Nothing is logged
+    Off,
+    /// Server errors, and requests that failed with a 5xx status
+    Error,
+    /// Server messages and all requests
+    Info,
+    /// Also logs the headers of each request
+    Debug,
+}
+
+impl FromStr for Level {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Level, String> {
+        match s {
+            "off" => Ok(Level::Off),
+            "error" => Ok(Level::Error),
+            "info" => Ok(Level::Info),
+            "debug" => Ok(Level::Debug),
+            _ => Err(format!("Unknown log level {:?}; expected off, error, info, or debug", s)),
+        }
+    }
+}
+
+impl fmt::Display for Level {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match *self {
+            Level::Off => "off",
+            Level::Error => "error",
+            Level::Info => "info",
+            Level::Debug => "debug",
+        };
+        f.write_str(name)
+    }
+}
+
+/// How access log entries are written.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Format {
+    /// Common Log Format, followed by the matched responder prefix, the latency in microseconds,
+    /// and the request ID
+    Common,
+    /// One JSON object per line
+    Json,
+}
+
+impl FromStr for Format {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Format, String> {
+        match s {
+            "common" => Ok(Format::Common),
+            "json" => Ok(Format::Json),
+            _ => Err(format!("Unknown log format {:?}; expected common or json", s)),
+        }
+    }
+}
+
+/// The details of a single handled request.
+pub struct Entry<'a> {
+    pub time: SystemTime,
+    pub request_id: usize,
+    pub remote_addr: Option<SocketAddr>,
+    pub method: &'a tiny_http::Method,
+    pub url: &'a str,
+    pub http_version: &'a tiny_http::HTTPVersion,
+    /// The prefix of the responder that handled the request, if any matched
+    pub prefix: Option<&'a str>,
+    pub status: u16,
+    /// Size of the response body in bytes, if known up front
+    pub size: Option<usize>,
+    pub latency: Duration,
+}
+
+#[derive(Serialize)]
+struct JsonEntry<'a> {
+    time: u64,
+    request_id: usize,
+    remote_addr: Option<String>,
+    method: String,
+    url: String,
+    prefix: Option<&'a str>,
+    status: u16,
+    size: Option<usize>,
+    latency_us: u64,
+}
+
+pub struct AccessLog {
+    format: Format,
+    level: Level,
+    sink: Mutex<Box<dyn Write + Send>>,
+}
+
+impl AccessLog {
+    pub fn new(format: Format, level: Level, sink: Box<dyn Write + Send>) -> AccessLog {
+        AccessLog { format, level, sink: Mutex::new(sink) }
+    }
+
+    /// Logs to stdout, or appends to the given file.
+    pub fn open(format: Format, level: Level, file: Option<&str>) -> io::Result<AccessLog> {
+        let sink: Box<dyn Write + Send> = match file {
+            Some(path) => Box::new(OpenOptions::new().create(true).append(true).open(path)?),
+            None => Box::new(io::stdout()),
+        };
+        Ok(AccessLog::new(format, level, sink))
+    }
+
+    pub fn enabled(&self, level: Level) -> bool {
+        level != Level::Off && level <= self.level
+    }
+
+    /// Records a handled request.
+    pub fn access(&self, entry: &Entry) {
+        let level = if entry.status >= 500 { Level::Error } else { Level::Info };
+        if !self.enabled(level) {
+            return;
+        }
+        let line = match self.format {
+            Format::Common => format_common(entry),
+            Format::Json => format_json(entry),
+        };
+        // Written in one call so lines from different workers don't interleave
+        let mut sink = self.sink.lock().unwrap_or_else(|e| e.into_inner());
+        let _ = sink.write_all(format!("{}\n", line).as_bytes()).and_then(|_| sink.flush());
+    }
+
+    /// Writes a server message to stderr, if the log is at least as verbose as `level`.
+    pub fn message(&self, level: Level, message: &str) {
+        if self.enabled(level) {
+            eprintln!("[{}] {}", level, message);
+        }
+    }
+
+    pub fn error(&self, message: &str) { self.message(Level::Error, message); }
+
+    pub fn info(&self, message: &str) { self.message(Level::Info, message); }
+
+    pub fn debug(&self, message: &str) { self.message(Level::Debug, message); }
+}
+
+/// Query parameters whose values are credentials (the admin token), and so aren't logged.
+const SECRET_PARAMS: &[&str] = &["token"];
+/// Headers whose values are credentials, and so aren't logged.
+const SECRET_HEADERS: &[&str] = &["Authorization", "Proxy-Authorization", "Cookie"];
+const REDACTED: &str = "REDACTED";
+
+/// The request URL as it should be logged, with the values of secret query parameters masked.
+pub fn redact_url(url: &str) -> String {
+    let (path, query) = match url.find('?') {
+        Some(i) => (&url[..i], &url[i + 1..]),
+        None => return url.to_string(),
+    };
+    let pairs: Vec<_> = query.split('&').map(|pair| {
+        match pair.find('=') {
+            Some(i) if SECRET_PARAMS.contains(&util::percent_decode(&pair[..i]).as_str()) =>
+                format!("{}={}", &pair[..i], REDACTED),
+            _ => pair.to_string(),
+        }
+    }).collect();
+    format!("{}?{}", path, pairs.join("&"))
+}
+
+/// A request header as it should be logged, with the values of credential headers masked.
+pub fn redact_header(header: &tiny_http::Header) -> String {
+    if SECRET_HEADERS.iter().any(|name| header.field.equiv(name)) {
+        format!("{}: {}", header.field, REDACTED)
+    } else {
+        header.to_string()
+    }
+}
+
+/// `host ident authuser [date] "request line" status bytes prefix latency_us request_id`
+fn format_common(entry: &Entry) -> String {
+    format!("{} - - [{}] \"{} {} HTTP/{}\" {} {} {} {} {}",
+            entry.remote_addr.map(|a| a.ip().to_string()).unwrap_or_else(|| "-".into()),
+            clf_time(entry.time),
+            entry.method, redact_url(entry.url), entry.http_version,
+            entry.status,
+            entry.size.map(|s| s.to_string()).unwrap_or_else(|| "-".into()),
+            entry.prefix.map(display_prefix).unwrap_or("-"),
+            entry.latency.as_micros(),
+            entry.request_id)
+}
+
+fn format_json(entry: &Entry) -> String {
+    let json = JsonEntry {
+        time: entry.time.duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0),
+        request_id: entry.request_id,
+        remote_addr: entry.remote_addr.map(|a| a.to_string()),
+        method: entry.method.to_string(),
+        url: redact_url(entry.url),
+        prefix: entry.prefix.map(display_prefix),
+        status: entry.status,
+        size: entry.size,
+        latency_us: entry.latency.as_micros() as u64,
+    };
+    serde_json::to_string(&json).expect("JsonEntry is always serializable")
+}
+
+/// The root responder is mounted at the empty prefix, which would be confusing in a log line.
+fn display_prefix(prefix: &str) -> &str {
+    if prefix.is_empty() { "/" } else { prefix }
+}
+
+/// Formats a time as `10/Oct/2000:13:55:36 +0000`, always in UTC.
+fn clf_time(time: SystemTime) -> String {
+    const MONTHS: [&str; 12] =
+        ["Jan", "Feb", "Mar", "Apr", "May", "Jun", "Jul", "Aug", "Sep", "Oct", "Nov", "Dec"];
+    let secs = time.duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0);
+    let (year, month, day) = civil_from_days((secs / 86_400) as i64);
+    let secs_of_day = secs % 86_400;
+    format!("{:02}/{}/{}:{:02}:{:02}:{:02} +0000", day, MONTHS[(month - 1) as usize], year,
+            secs_of_day / 3600, secs_of_day / 60 % 60, secs_of_day % 60)
+}
+
+/// Converts days since the Unix epoch to a (year, month, day) date, see
+/// http://howardhinnant.github.io/date_algorithms.html#civil_from_days
+fn civil_from_days(days: i64) -> (i64, u32, u32) {
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let day_of_era = z.rem_euclid(146_097);
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let mp = (5 * day_of_year + 2) / 153;
+    let day = (day_of_year - (153 * mp + 2) / 5 + 1) as u32;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 } as u32;
+    let year = year_of_era + era * 400 + if month <= 2 { 1 } else { 0 };
+    (year, month, day)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Arc;
+
+    /// A sink tests can read back from
+    #[derive(Clone)]
+    struct Buffer(Arc<Mutex<Vec<u8>>>);
+    impl Write for Buffer {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> { self.0.lock().unwrap().write(buf) }
+        fn flush(&mut self) -> io::Result<()> { Ok(()) }
+    }
+    impl Buffer {
+        fn contents(&self) -> String { String::from_utf8(self.0.lock().unwrap().clone()).unwrap() }
+    }
+
+    fn entry<'a>(method: &'a tiny_http::Method, version: &'a tiny_http::HTTPVersion, status: u16)
+            -> Entry<'a> {
+        Entry {
+            time: UNIX_EPOCH + Duration::from_secs(971_186_136), // 2000-10-10T13:55:36Z
+            request_id: 42,
+            remote_addr: Some("127.0.0.1:54321".parse().unwrap()),
+            method,
+            url: "/raw/foo?bar",
+            http_version: version,
+            prefix: Some("/raw"),
+            status,
+            size: Some(2326),
+            latency: Duration::from_micros(1500),
+        }
+    }
+
+    #[test]
+    fn common_format() {
+        let (method, version) = (tiny_http::Method::Get, tiny_http::HTTPVersion(1, 1));
+        assert_eq!(format_common(&entry(&method, &version, 200)),
+                   "127.0.0.1 - - [10/Oct/2000:13:55:36 +0000] \"GET /raw/foo?bar HTTP/1.1\" 200 \
+                    2326 /raw 1500 42");
+    }
+
+    #[test]
+    fn json_format() {
+        let (method, version) = (tiny_http::Method::Post, tiny_http::HTTPVersion(1, 0));
+        let mut entry = entry(&method, &version, 404);
+        entry.prefix = None;
+        entry.size = None;
+        assert_eq!(format_json(&entry),
+                   "{\"time\":971186136,\"request_id\":42,\"remote_addr\":\"127.0.0.1:54321\",\
+                    \"method\":\"POST\",\"url\":\"/raw/foo?bar\",\"prefix\":null,\"status\":404,\
+                    \"size\":null,\"latency_us\":1500}");
+    }
+
+    #[test]
+    fn secrets_are_redacted() {
+        let (method, version) = (tiny_http::Method::Get, tiny_http::HTTPVersion(1, 1));
+        let mut entry = entry(&method, &version, 200);
+        entry.url = "/quit?a=1&token=SECRET&to%6Ben=SECRET2&tokens=b";
+        for line in &[format_common(&entry), format_json(&entry)] {
+            assert!(!line.contains("SECRET"), "{}", line);
+            let redacted = "/quit?a=1&token=REDACTED&to%6Ben=REDACTED&tokens=b";
+            assert!(line.contains(redacted), "{}", line);
+        }
+        assert_eq!(redact_url("/raw/foo?bar"), "/raw/foo?bar");
+
+        let header = |h: &str| redact_header(&h.parse().unwrap());
+        assert_eq!(header("Authorization: Bearer SECRET"), "Authorization: REDACTED");
+        assert_eq!(header("cookie: session=SECRET"), "cookie: REDACTED");
+        assert_eq!(header("Accept: */*"), "Accept: */*");
+    }
+
+    #[test]
+    fn levels() {
+        let (method, version) = (tiny_http::Method::Get, tiny_http::HTTPVersion(1, 1));
+        let buffer = Buffer(Arc::new(Mutex::new(Vec::new())));
+        let log = AccessLog::new(Format::Common, Level::Error, Box::new(buffer.clone()));
+        log.access(&entry(&method, &version, 200));
+        assert_eq!(buffer.contents(), "");
+        log.access(&entry(&method, &version, 503));
+        assert_eq!(buffer.contents().lines().count(), 1);
+
+        let off = AccessLog::new(Format::Common, Level::Off, Box::new(buffer.clone()));
+        off.access(&entry(&method, &version, 503));
+        assert_eq!(buffer.contents().lines().count(), 1);
+        assert!(!off.enabled(Level::Off));
+
+        assert!(Level::Debug > Level::Info);
+        assert_eq!("debug".parse::<Level>(), Ok(Level::Debug));
+        assert!("loud".parse::<Level>().is_err());
+    }
+
+    #[test]
+    fn dates() {
+        assert_eq!(civil_from_days(0), (1970, 1, 1));
+        assert_eq!(civil_from_days(11_016), (2000, 2, 29));
+        assert_eq!(civil_from_days(-1), (1969, 12, 31));
+        assert_eq!(clf_time(UNIX_EPOCH), "01/Jan/1970:00:00:00 +0000");
+    }
+}
@@ -12,139 +12,280 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
-/// A set of macros that provide a basic Dependency Injection pattern
-/// Always fully-qualify imports here so callers don't need to add unnecessary use statements
-/// See also https://doc.rust-lang.org/book/first-edition/ufcs.html wrt invoking traits
+//! A set of macros that provide a basic Dependency Injection pattern
+//! Always fully-qualify imports here so callers don't need to add unnecessary use statements
+//! See also https://doc.rust-lang.org/book/first-edition/ufcs.html wrt invoking traits
 
 /// Constructs a "binder", a struct that can hold arbitrary types, installed via the bind! macro.
+/// Values are keyed by the `TypeId` of their binding trait, so bindings with the same name in
+/// different modules don't collide, and a key always maps to a value of the binding's type.
 ///   Usage: binder!(BinderTypeName)
 ///     BinderTypeName:  Name of the struct to define.
+#[macro_export]
 macro_rules! binder {
     ($store:ident) => {
-        struct $store {
-            store: ::std::collections::HashMap<String, Box<::std::any::Any>>
-        }
+        binder!(@struct $store);
 
         impl $store {
             fn new() -> $store {
                 $store { store: ::std::collections::HashMap::new() }
             }
         }
-    }
+    };
+    // Just the struct, without a constructor
+    (@struct $store:ident) => {
+        struct $store {
+            /// Each value is stored with its binding's name, which is only used in error messages
+            store: ::std::collections::HashMap<
+                ::std::any::TypeId, (&'static str, Box<dyn (::std::any::Any)>)>
+        }
+    };
 }
 
 /// Binds a value to to a binder instance - effectively just a wrapper for
 ///   BindingTrait::put(&mut binder, value)
-/// but can be used for consistency with the other macro APIs
+/// but can be used for consistency with the other macro APIs. Prefer a bindings!() builder,
+/// which checks every binding is supplied.
 ///   Usage: bind!(store, BindingTrait, Binding)
 ///     BinderInstance:  A Binder instance, where the binding will be stored
 ///     BindingTrait:    Trait which will provide Binding
 ///     Binding:         Instance to bind to the BindingTrait
+#[allow(unused_macros)]
+#[macro_export]
 macro_rules! bind {
     ($map:ident, $bnd:ident, $value:expr) => {
         $bnd::put(&mut $map, $value);
     }
 }
 
-/// Registers a binding, creating a trait with the given name
+/// Registers a binding, creating a trait with the given name. The trait object type
+/// `dyn BindingTraitName` is the binding's key in the binder.
 ///   Usage: binding!(BinderType, BindingTraitName, BindingType)
 ///     BinderType:        A binder type, created by binder!()
 ///     BindingTraitName:  Trait to create that will provide the given binding
 ///     BindingType:       Type that BindingTrait will provide
+#[macro_export]
 macro_rules! binding {
     ($store:ident, $name:ident, $ty:ty) => {
         trait $name { fn get(&self) -> &$ty; fn put(&mut self, value: $ty); }
 
         impl $name for $store {
             fn get(&self) -> &$ty {
-                match self.store.get(stringify!($name).into()) {
-                    Some(dep) => { match dep.downcast_ref::<$ty>() {
-                        Some(dep) => dep,
-                        None => panic!("Could not downcast {} to {} - wrong binding! type?",
-                            stringify!($name), stringify!($ty))
+                // Only put() inserts under this key, so the value is always a $ty
+                match self.store.get(&::std::any::TypeId::of::<dyn $name>())
+                        .and_then(|&(_, ref dep)| dep.downcast_ref::<$ty>()) {
+                    Some(dep) => dep,
+                    None => {
+                        let bound: Vec<_> = self.store.values().map(|&(name, _)| name).collect();
+                        panic!("{} has no binding for {}!\n\tBound types: {:?}\n",
+                               stringify!($store), stringify!($name), bound)
                     }
-                    },
-                    None => panic!("{} has no binding for {}!\n\tBound types: {:?}\n",
-                         stringify!($store), stringify!($name), self.store.keys())
                 }
             }
             fn put(&mut self, value: $ty) {
-                match self.store.entry(stringify!($name).into()) {
-                    ::std::collections::hash_map::Entry::Occupied(entry) => {
-                        let existing: &$ty = entry.get().downcast_ref::<$ty>().unwrap();
-                        panic!(
-                            "Conflicting binding for {}; cannot bind to {:?} already bound to {:?}",
-                            stringify!($bnd), value, existing)
+                match self.store.entry(::std::any::TypeId::of::<dyn $name>()) {
+                    ::std::collections::hash_map::Entry::Occupied(_) => {
+                        panic!("Conflicting binding for {}; cannot bind to {:?}, already bound",
+                               stringify!($name), value)
                     },
                     ::std::collections::hash_map::Entry::Vacant(entry) => {
-                        entry.insert(Box::new(value) as Box<::std::any::Any>);
+                        entry.insert((stringify!($name),
+                                      Box::new(value) as Box<dyn (::std::any::Any)>));
                     }
                 }
-                //self.store.insert(stringify!($name).into(), Box::new(value) as Box<Any>);
             }
         }
+
+        impl $crate::inject::Binding for dyn $name { type Value = $ty; }
+
+        impl $crate::inject::Provides<dyn $name> for $store {
+            fn provide(&self) -> &$ty { $name::get(self) }
+        }
     }
 }
 
-// Registers a provider of a binding, introducing a recursive dependency on another binding
-// Note this can only provide references, not owned types (because the closure would be the owner,
-// and it goes out of scope upon returning).
-//   Usage: provider!(BinderType, ProviderTraitName, ProviderType, DependantTrait, Closure)
+/// Marks a binding that a `bindings!` builder hasn't been given yet.
+pub struct Unbound;
+/// Marks a binding that a `bindings!` builder has been given.
+pub struct Bound;
+
+/// Defines a binder (see binder!) and registers several bindings in it (see binding!), along with
+/// a builder that checks at compile time that every binding is supplied exactly once. The builder
+/// has a type parameter per binding, which each setter changes from `Unbound` to `Bound`, and
+/// `build()` only exists once they're all `Bound` - so forgetting a binding is a type error rather
+/// than a "no binding" panic when a request comes in. The binder has no constructor of its own, so
+/// the builder is the only way to create one.
+///   Usage: bindings!(BinderType, BuilderTypeName { setter: BindingTraitName => BindingType, ... })
+///     BinderType:        Name of the binder struct to define
+///     BuilderTypeName:   Name of the builder struct to define
+///     setter:            Name of the builder method that supplies the binding
+///     BindingTraitName:  Trait to create that will provide the given binding
+///     BindingType:       Type that BindingTrait will provide
+///
+/// ```
+/// #[macro_use] extern crate rivet;
+///
+/// bindings!(Deps, DepsBuilder { name: Name => String, count: Count => u32 });
+///
+/// fn main() {
+///     let deps = DepsBuilder::new().count(3).name("Foo".to_string()).build();
+///     assert_eq!(Name::get(&deps), "Foo");
+///     assert_eq!(*Count::get(&deps), 3);
+/// }
+/// ```
+///
+/// Leaving a binding unset is a compile error:
+///
+/// ```compile_fail
+/// #[macro_use] extern crate rivet;
+///
+/// bindings!(Deps, DepsBuilder { name: Name => String, count: Count => u32 });
+///
+/// fn main() {
+///     // error: no `build` for DepsBuilder<Bound, Unbound>
+///     let deps = DepsBuilder::new().name("Foo".to_string()).build();
+/// }
+/// ```
+///
+/// As is skipping the builder:
+///
+/// ```compile_fail
+/// #[macro_use] extern crate rivet;
+///
+/// bindings!(Deps, DepsBuilder { name: Name => String, count: Count => u32 });
+///
+/// fn main() {
+///     // error: no function `new` for Deps
+///     let deps = Deps::new();
+/// }
+/// ```
+#[macro_export]
+macro_rules! bindings {
+    ($store:ident, $builder:ident { $($method:ident: $name:ident => $ty:ty),+ $(,)* }) => {
+        binder!(@struct $store);
+        $( binding!($store, $name, $ty); )+
+
+        // The type parameters are named after the bindings they track
+        struct $builder<$($name),+> {
+            deps: $store,
+            _bound: ::std::marker::PhantomData<($($name,)+)>,
+        }
+
+        impl $builder<$(bindings!(@unbound $name)),+> {
+            fn new() -> Self {
+                let deps = $store { store: ::std::collections::HashMap::new() };
+                $builder { deps, _bound: ::std::marker::PhantomData }
+            }
+        }
+
+        impl $builder<$(bindings!(@bound $name)),+> {
+            fn build(self) -> $store { self.deps }
+        }
+
+        bindings!(@setters $builder [] $($method: $name => $ty),+);
+    };
+    // Generates the setter for the first remaining binding, which only applies while that binding
+    // is Unbound, leaving the other bindings' states unchanged
+    (@setters $builder:ident [$($before:ident)*] $method:ident: $name:ident => $ty:ty
+            $(, $rest_method:ident: $rest:ident => $rest_ty:ty)*) => {
+        impl<$($before,)* $($rest),*> $builder<$($before,)* $crate::macros::Unbound $(, $rest)*> {
+            fn $method(mut self, value: $ty) -> $builder<$($before,)* $crate::macros::Bound $(, $rest)*> {
+                $name::put(&mut self.deps, value);
+                $builder { deps: self.deps, _bound: ::std::marker::PhantomData }
+            }
+        }
+
+        bindings!(@setters $builder [$($before)* $name] $($rest_method: $rest => $rest_ty),*);
+    };
+    (@setters $builder:ident [$($before:ident)*]) => {};
+    (@unbound $name:ident) => { $crate::macros::Unbound };
+    (@bound $name:ident) => { $crate::macros::Bound };
+}
+
+/// Registers a provider of a binding, introducing a recursive dependency on another binding
+/// Note this can only provide references, not owned types (because the closure would be the owner,
+/// and it goes out of scope upon returning).
+///   Usage: provider!(BinderType, ProviderTraitName, ProviderType, DependantTrait, Closure)
 ///     BinderType:         A binder type, created by binder!()
 ///     ProviderTraitName:  Trait to create that will provide the given binding
 ///     ProviderType:       Type that ProviderTrait will provide
 ///     DependantTrait:     Binding trait that the provider depends on
 ///     Closure:            A closure of the form |d: &'a DependantTrait| ... that returns a
 ///                         reference to an value of ProviderType
-/// TODO can closure signature be simplified?
+// TODO can closure signature be simplified?
+#[macro_export]
 macro_rules! provider {
     ($store:ident, $name:ident, $ty:ty, $dep:ty, $provider_fn:expr) => {
         trait $name { fn get(&self) -> &$ty; }
 
         impl $name for $store {
-            fn get<'a>(&'a self) -> &$ty {
-                &$provider_fn(self as &$dep)
+            // Closures may name the dependency as a bare trait, e.g. |d: &'a UrlParts|
+            #[allow(bare_trait_objects)]
+            fn get<'a>(&'a self) -> &'a $ty {
+                $provider_fn(self as &$dep)
             }
         }
-    }
-}
 
-// Invokes a func with n repetitions of the given argument
-/// See also http://danielkeep.github.io/tlborm/book/pat-push-down-accumulation.html, but I don't
-/// think that pattern makes this use-case much cleaner. And
-/// https://stackoverflow.com/q/33173235/113632.
-/// Also posted to https://stackoverflow.com/q/47767910/113632
-macro_rules! call_n {
-    ($func:ident, $arg:expr, 0) => { $func() };
-    ($func:ident, $arg:expr, 1) => { $func($arg) };
-    ($func:ident, $arg:expr, 2) => { $func($arg, $arg) };
-    ($func:ident, $arg:expr, 3) => { $func($arg, $arg, $arg) };
+        impl $crate::inject::Binding for dyn $name { type Value = $ty; }
+
+        impl $crate::inject::Provides<dyn $name> for $store {
+            fn provide(&self) -> &$ty { $name::get(self) }
+        }
+    }
 }
 
-/// Support arbitrary injections by wrapping a call to the given func in a closure that takes a
-/// BinderType and passes it to all parameters of the function.
-///   Usage: inject(BinderType, InjectedFunction, NumArguments
+/// Wraps a call to the given func in a closure that takes a BinderType, resolving each of the
+/// function's parameters (of type `inject::Dep<dyn BindingTrait>`) from the binder. Functions of
+/// up to 12 parameters are supported; see `inject::Injectable`.
+///   Usage: inject!(BinderType, InjectedFunction)
 ///     BinderType:        A binder type, created by binder!()
-///     InjectedFunction:  A function that takes 0 or more arguments, all of BinderType traits
-///     NumArguments:      The number of arguments the function takes
+///     InjectedFunction:  A function whose parameters are all bound by BinderType
 #[allow(unused_macros)]
+#[macro_export]
 macro_rules! inject {
-    ($store:ident, $func:ident, $num_args:tt) => {
-        |_deps: &$store| util::success(&call_n!($func, _deps, $num_args))
+    ($store:ident, $func:expr) => {
+        |deps: &$store| $crate::inject::Injectable::inject(&$func, deps)
     };
 }
 /// Same as inject!, but the closure is Boxed
 #[allow(unused_macros)]
+#[macro_export]
 macro_rules! inject_box {
-    ($store:ident, $func:ident, $num_args:tt) => { Box::new(inject!($store, $func, $num_args)) }
+    ($store:ident, $func:expr) => { Box::new(inject!($store, $func)) }
 }
-/// Same as inject!, but transforms the result into a ResponseBox too
+/// Same as inject_box!, but transforms the function's result into a successful Responder result
+#[macro_export]
 macro_rules! inject_http_success {
-    ($store:ident, $func:ident, $num_args:tt) => {
-        Box::new(|_deps: &$store| util::success(&call_n!($func, _deps, $num_args)))
+    ($store:ident, $func:expr) => {
+        Box::new(|deps: &$store| {
+            util::ResponseBuilder::ok().text(&$crate::inject::Injectable::inject(&$func, deps)).build()
+        })
     };
 }
 
+/// Invokes the given macro once for each arity from 0 to 12, passing it that many type parameter
+/// names, to implement a trait for functions of any arity (e.g. `inject::Injectable`).
+///   Usage: for_each_arity!(MacroName)
+///     MacroName:  A macro taking a comma-separated list of type parameter names
+#[macro_export]
+macro_rules! for_each_arity {
+    ($mac:ident) => {
+        $mac!();
+        $mac!(A);
+        $mac!(A, B);
+        $mac!(A, B, C);
+        $mac!(A, B, C, D);
+        $mac!(A, B, C, D, E);
+        $mac!(A, B, C, D, E, F);
+        $mac!(A, B, C, D, E, F, G);
+        $mac!(A, B, C, D, E, F, G, H);
+        $mac!(A, B, C, D, E, F, G, H, I);
+        $mac!(A, B, C, D, E, F, G, H, I, J);
+        $mac!(A, B, C, D, E, F, G, H, I, J, K);
+        $mac!(A, B, C, D, E, F, G, H, I, J, K, L);
+    }
+}
+
 #[cfg(test)]
 mod tests {
     binder!(MyDeps);
@@ -156,8 +297,8 @@ mod tests {
         let mut deps = MyDeps::new();
         bind!(deps, MyBinding, "FooBar".to_string());
 
-        let my_binding: &MyBinding = &deps;
-        let my_provided_binding: &ProvidedBinding = &deps;
+        let my_binding: &dyn MyBinding = &deps;
+        let my_provided_binding: &dyn ProvidedBinding = &deps;
         assert_eq!(my_binding.get(), "FooBar");
         assert_eq!(my_provided_binding.get(), "Foo");
     }
@@ -166,9 +307,57 @@ mod tests {
     #[should_panic(expected = "MyDeps has no binding for MyBinding!")]
     fn basic_di_missing_binding() {
         let deps = MyDeps::new();
-        let my_binding: &MyBinding = &deps;
+        let my_binding: &dyn MyBinding = &deps;
         my_binding.get();
     }
 
+    #[test]
+    #[should_panic(expected = "Conflicting binding for MyBinding")]
+    fn basic_di_conflicting_binding() {
+        let mut deps = MyDeps::new();
+        bind!(deps, MyBinding, "Foo".to_string());
+        bind!(deps, MyBinding, "Bar".to_string());
+    }
+
+    // Bindings are keyed by trait, not by name, so identically-named bindings in different
+    // modules are independent even when they share a binder
+    binder!(SharedDeps);
+
+    mod first {
+        use super::SharedDeps;
+        binding!(SharedDeps, Name, String);
+        pub fn bind(deps: &mut SharedDeps) { Name::put(deps, "first".to_string()); }
+        pub fn get(deps: &SharedDeps) -> &str { Name::get(deps) }
+    }
+
+    mod second {
+        use super::SharedDeps;
+        binding!(SharedDeps, Name, u32);
+        pub fn bind(deps: &mut SharedDeps) { Name::put(deps, 2); }
+        pub fn get(deps: &SharedDeps) -> u32 { *Name::get(deps) }
+    }
+
+    #[test]
+    fn same_name_different_modules() {
+        let mut deps = SharedDeps::new();
+        first::bind(&mut deps);
+        second::bind(&mut deps);
+        assert_eq!(first::get(&deps), "first");
+        assert_eq!(second::get(&deps), 2);
+    }
+
+    bindings!(BuiltDeps, BuiltDepsBuilder {
+        name: Name => String,
+        count: Count => u32,
+    });
+
+    #[test]
+    fn builder() {
+        // Bindings can be supplied in any order, but build() only compiles once they all are
+        let deps = BuiltDepsBuilder::new().count(3).name("Foo".to_string()).build();
+        assert_eq!(Name::get(&deps), "Foo");
+        assert_eq!(*Count::get(&deps), 3);
+    }
+
     // TODO more tests
 }
\ No newline at end of file
@@ -13,106 +13,275 @@
 // limitations under the License.
 
 #[macro_use] extern crate lazy_static;
-#[macro_use] mod macros;
+#[macro_use] extern crate rivet;
 
+#[macro_use] extern crate rivet_codegen;
+#[macro_use] extern crate serde_derive;
+
+extern crate ctrlc;
+extern crate httpdate;
 extern crate regex;
+extern crate regex_syntax;
+extern crate serde;
+extern crate serde_json;
+extern crate serde_urlencoded;
 extern crate tiny_http;
+extern crate toml;
+extern crate uuid;
 
-use std::collections::HashMap;
+use error::RivetError;
+use rivet::inject;
+use std::env;
+use std::process;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::{Instant, SystemTime};
 use tiny_http::{Server};
 
+mod config;
+mod error;
+mod logging;
+mod mount;
+mod pool;
+mod recover;
+mod request;
 mod responders;
 mod util;
 
 /// Server entry point - starts up a web server and routes requests to the known responders.
 ///
 /// This is essentially a meta-plugin layer, in order to support different plugin paridigms in the
-/// same server. Each `Responder` is mapped to a URL prefix (e.g. all `/raw` URLs are routed to the
-/// `Raw` responder) and if a request matches a registered prefix it's routed to the associated
-/// `Responder` to compute a `Response`. Complex `Responder` implementations will generally then
-/// implement an "actual" plugin model and provide a nicer API for processing individual requests.
+/// same server. Each `Responder` is mounted at a URL prefix (e.g. all `/raw` URLs are routed to the
+/// `Raw` responder) and if a request matches a mounted prefix it's routed to the associated
+/// `Responder` to compute a `Response`; see `mount::MountTable` for how prefixes are matched.
+/// Complex `Responder` implementations will generally then implement an "actual" plugin model and
+/// provide a nicer API for processing individual requests.
 ///
 /// For example `/nice-plugin/foo` and `/nice-plugin/bar` would both be routed to the `NicePlugin`
 /// responder (assuming such a responder is installed) but each path might be handled by different
 /// code paths registered with the `NicePlugin` responder.
+///
+/// Which responders are enabled, and where the server listens, is controlled by `config::Config`;
+/// run with `--help` for details.
 fn main() {
-    // Register responders here
+    let config = match config::load(env::args().skip(1), |k| env::var(k).ok()) {
+        Ok(config::Command::Run(config)) => config,
+        Ok(config::Command::Help) => {
+            print!("{}", config::usage());
+            return;
+        },
+        Err(e) => {
+            eprintln!("{}\nRun with --help for usage.", e);
+            process::exit(2);
+        },
+    };
+
+    // Triggered by SIGINT/SIGTERM or the admin /quit endpoint
+    let stopper = pool::Stopper::new();
+
+    let log = match logging::AccessLog::open(
+            config.log_format, config.log_level, config.log_file.as_deref()) {
+        Ok(log) => Arc::new(log),
+        Err(e) => {
+            eprintln!("Failed to open log file {:?}: {}", config.log_file, e);
+            process::exit(1);
+        },
+    };
+
+    // Mount the enabled responders; new responders should be added to `responders::REGISTRY`
     let responders = {
-        let mut m: HashMap<String, Box<responders::Responder>> = HashMap::new();
-        m.insert("".into(), Box::new(RootResponder {}));
-        m.insert("closure".into(), Box::new(responders::closure::Closure {}));
-        m.insert("factory".into(), Box::new(responders::factory::Factory::new()));
-        m.insert("pattern".into(), Box::new(responders::pattern::Pattern {}));
-        m.insert("raw".into(), Box::new(responders::raw::Raw {}));
-        m.insert("stringly".into(), Box::new(responders::stringly::Stringly {}));
-        m.insert("traits".into(), Box::new(responders::traits::Traits {}));
-        m.insert("traits_macro".into(), Box::new(responders::traits_macro::TraitsMacro {}));
-        m // now the map is immutable
+        let mut m = mount::MountTable::new();
+        let enabled: Vec<_> = config.responders.iter()
+            .map(|name| responders::registration(name).expect("Config validates responder names"))
+            .collect();
+        let mut problems = 0;
+        for registration in &enabled {
+            let responder = (registration.create)();
+            for problem in responder.diagnostics() {
+                log.error(&format!("/{}: {}", registration.name, problem));
+                problems += 1;
+            }
+            m.mount(registration.name, responder);
+        }
+        if problems > 0 && config.strict {
+            log.error(&format!("refusing to start with {} startup problem(s) (--strict)",
+                               problems));
+            process::exit(1);
+        }
+        m.mount("/", Box::new(RootResponder { enabled }));
+        if let Some(ref dir) = config.static_dir {
+            match responders::static_files::StaticFiles::new(dir) {
+                Ok(files) => m.mount(&config.static_prefix,
+                                     Box::new(files.listing(config.static_listing))),
+                Err(e) => {
+                    log.error(&format!("Failed to serve static files from {:?}: {}", dir, e));
+                    process::exit(1);
+                },
+            }
+        }
+        if let Some(ref token) = config.admin_token {
+            let quit = responders::admin::Quit::new(token.clone(), stopper.clone());
+            m.mount("/quit", Box::new(quit));
+        }
+        m // now the table is immutable
     };
 
     // Start server
     // OSX prompts to permit cargo to listen on a port every time `cargo run` is called
     // https://apple.stackexchange.com/a/150711/69703 resolves this:
     //   sudo codesign --force --deep --sign - $(which cargo)
-    let server = Server::http("0.0.0.0:8000").unwrap();
-    println!("server started: http://localhost:8000");
-
-    // Single-threaded server - tiny_http supports multi-threading, but it's not necessary for the
-    // initial proof-of-concept
-    for request in server.incoming_requests() {
-        // TODO logging framework?
-        print!("received {:?} request for url {:?}", request.method(), request.url());
-
-        // Fallback shutdown mechanism in case Ctrl+C isn't propagated properly.
-        // According to https://github.com/rust-lang/cargo/issues/2343 it should be, but at least on
-        // my system it's not working - might be https://github.com/rust-lang/cargo/issues/4575
-        if request.url() == "/quit" {
-            let _ = request.respond(util::success("Shutting Down!"));
-            println!();
-            break;
+    let server = match Server::http(config.listen_addr()) {
+        Ok(server) => Arc::new(server),
+        Err(e) => {
+            log.error(&format!("Failed to listen on {}: {}", config.listen_addr(), e));
+            process::exit(1);
+        },
+    };
+    let workers = config.workers;
+    let max_body_size = config.max_body_size;
+    log.info(&format!("server started: http://{} ({} workers)", server.server_addr(), workers));
+
+    // A second signal skips draining, in case a request is stuck
+    let signalled = stopper.clone();
+    ctrlc::set_handler(move || {
+        if !signalled.stop() {
+            process::exit(130);
         }
+    }).expect("Failed to install signal handler");
 
-        // Lookup the right responder for the request
-        let url_prefix = url_prefix(&request.url()).to_string();
-        let response = match responders.get(&url_prefix) {
-            Some(responder) => {
-                if url_prefix.len() > 0 { print!(" - routed to {}", url_prefix); }
-                responder.handle(&request)
-            },
-            _ => util::fail404("No responder found")
-        };
-        println!();
+    // Requests are handled concurrently by a pool of worker threads which share the responders
+    recover::install_hook();
+    let request_log = log.clone();
+    let pool = pool::WorkerPool::start(workers, &server, &stopper, &log, move |request| {
+        handle(&responders, &request_log, max_body_size, request)
+    });
+
+    stopper.wait();
+    log.info(&format!("shutting down, waiting up to {:?} for in-flight requests",
+                      config.shutdown_timeout));
+    // Dropping the last reference to `server` closes the listening socket, so no new connections
+    // are accepted while the workers drain
+    drop(server);
+    let busy = pool.join_timeout(config.shutdown_timeout);
+    if busy > 0 {
+        log.error(&format!("{} request(s) still in flight after {:?}, exiting anyway",
+                           busy, config.shutdown_timeout));
+        process::exit(1);
+    }
+}
+
+/// Used to generate an ID for each request, so failures can be correlated with the logs.
+static NEXT_REQUEST_ID: AtomicUsize = AtomicUsize::new(1);
 
-        // Note that respond takes ownership of request at this point (self vs. &self)
-        let _ = request.respond(response); // ignore Result, it's a client-side error
+/// Routes a single request to the matching responder, sends its response, and logs the result.
+///
+/// The request body is read up front, and if it's larger than `max_body_size` the request is
+/// rejected with a 413 without being routed. Malformed request targets are rejected with a 400.
+/// Errors returned by the responder are converted to responses by `RivetError::into_response()`;
+/// server errors are also logged. A panic while routing or handling the request is caught and
+/// turned into a 500 response containing the request ID; the panic itself is logged, and the
+/// server keeps running.
+fn handle(responders: &mount::MountTable, log: &logging::AccessLog, max_body_size: usize,
+          mut request: tiny_http::Request) {
+    let start = Instant::now();
+    let request_id = NEXT_REQUEST_ID.fetch_add(1, Ordering::Relaxed);
+    if log.enabled(logging::Level::Debug) {
+        let headers: Vec<_> = request.headers().iter().map(logging::redact_header).collect();
+        log.debug(&format!("request {}: {} {} headers: {:?}", request_id, request.method(),
+                           logging::redact_url(request.url()), headers));
     }
-    // When `server` goes out of scope the server is shut down
+
+    let body = request::Body::read(&mut request, max_body_size);
+
+    // Lookup the right responder for the request
+    let mut prefix = None;
+    let result = recover::catch(|| {
+        let request = request::Request::new(&request, body?);
+        let url = util::UrlParts::parse(request.url())?;
+        if url.is_asterisk() {
+            return server_options(request.method());
+        }
+        match responders.route(&url) {
+            Some(mount) => {
+                prefix = Some(mount.prefix);
+                mount.responder.handle(&request, &mount.remainder)
+            },
+            _ => Err(RivetError::NotFound("No responder found".into()))
+        }
+    });
+    let response = match result {
+        Ok(Ok(response)) => response,
+        Ok(Err(error)) => {
+            if error.status_code() >= 500 {
+                log.error(&format!("request {} ({} {}) failed: {}", request_id,
+                                   request.method(), logging::redact_url(request.url()), error));
+            }
+            error.into_response()
+        },
+        Err(panic) => {
+            log.error(&format!("request {} ({} {}) panicked at {}: {}", request_id,
+                               request.method(), logging::redact_url(request.url()),
+                               panic.location.as_deref().unwrap_or("unknown location"),
+                               panic.message));
+            internal_error(request_id)
+        },
+    };
+    let (status, size) = (response.status_code().0, response.data_length());
+
+    // The request is consumed by respond(), so record what we need to log first
+    let (method, url, http_version, remote_addr) = (request.method().clone(),
+        request.url().to_string(), request.http_version().clone(), request.remote_addr().cloned());
+
+    // Note that respond takes ownership of request at this point (self vs. &self)
+    let _ = request.respond(response); // ignore Result, it's a client-side error
+
+    log.access(&logging::Entry {
+        time: SystemTime::now(),
+        request_id,
+        remote_addr,
+        method: &method,
+        url: &url,
+        http_version: &http_version,
+        prefix,
+        status,
+        size,
+        latency: start.elapsed(),
+    });
 }
 
-/// Get the first section of a URL, effectively matching the pattern `/([^/]+)/.*`.
-fn url_prefix(url: &str) -> &str {
-    let without_slash = &url[1..];
-    match without_slash.find(|c| c == '/' || c == '?') {
-        Some(index) => &without_slash[..index],
-        None => without_slash,
+/// Responds to `OPTIONS *`, which asks about the server as a whole rather than any one responder.
+fn server_options(method: &tiny_http::Method) -> responders::Response {
+    if *method != tiny_http::Method::Options {
+        return Err(RivetError::BadRequest("Only OPTIONS requests may target *".into()));
     }
+    util::ResponseBuilder::ok().build()
 }
 
-/// A responder for the homepage (`/`)
-struct RootResponder {}
+/// The response sent when handling a request panics. Only the request ID is exposed to the client;
+/// the details are in the server log.
+fn internal_error(request_id: usize) -> tiny_http::ResponseBox {
+    tiny_http::Response::from_string(format!("Internal Server Error (request ID {})", request_id))
+        .with_status_code(tiny_http::StatusCode::from(500))
+        .with_header(tiny_http::Header::from_bytes(&b"X-Request-Id"[..], request_id.to_string())
+            .expect("Request IDs are valid header values"))
+        .boxed()
+}
+
+/// A responder for the homepage (`/`), which also receives any URL no other responder matched
+struct RootResponder {
+    enabled: Vec<&'static responders::Registration>,
+}
 impl responders::Responder for RootResponder {
-    fn handle(&self, _request: &tiny_http::Request) -> tiny_http::ResponseBox {
+    fn handle(&self, _request: &request::Request, url: &util::UrlParts)
+            -> responders::Response {
+        if !url.path_components().is_empty() {
+            return Err(RivetError::NotFound("No responder found".into()));
+        }
         // TODO better names / clearer descriptions
-        util::success_html(
-            "<ul>
-            <li><a href=\"/raw/foo/bar?baz\">Raw</a> - handle Request object directly</li>
-            <li><a href=\"/stringly/foo/bar?baz\">Stringly</a> - pass in fixed request details</li>
-            <li><a href=\"/pattern/foo/bar?baz\">Pattern</a> - route requests by regex patterns</li>
-            <li><a href=\"/closure/both/bar?baz\">Closure</a> - route requests to user-specified closures</li>
-            <li><a href=\"/factory/\">Factory</a> - DI pattern using generic factory</li>
-            <li><a href=\"/traits/bar?baz\">Traits</a> - DI pattern providing some type safety via traits</li>
-            <li><a href=\"/traits_macro/bar?baz\">Traits Macro</a> - same as above, but simplified by macros</li>
-            </ul>"
-        )
+        let links: Vec<_> = self.enabled.iter()
+            .map(|r| format!("<li><a href=\"/{}{}\">{}</a> - {}</li>",
+                             r.name, r.example, r.title, r.description))
+            .collect();
+        util::ResponseBuilder::ok().html(&format!("<ul>\n{}\n</ul>", links.join("\n"))).build()
     }
 }
@@ -0,0 +1,137 @@
+// Copyright 2017 Google LLC, Matthew Vilim
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//      http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Maps URL prefixes to the `Responder` mounted there.
+
+use responders::Responder;
+use std::collections::HashMap;
+use util;
+
+/// A set of responders, each mounted at a (possibly multi-segment) URL prefix such as `/raw` or
+/// `/api/v2`. Requests are routed to the responder with the longest prefix matching whole path
+/// segments, so mounts can be nested - `/api/v2/foo` goes to `/api/v2` even if `/api` is also
+/// mounted, but `/api/v2foo` does not.
+pub struct MountTable {
+    mounts: HashMap<String, Box<dyn Responder>>,
+}
+
+/// The result of routing a URL - the matched prefix, its responder, and the rest of the URL.
+pub struct Mount<'a> {
+    pub prefix: &'a str,
+    pub responder: &'a dyn Responder,
+    pub remainder: util::UrlParts,
+}
+
+impl MountTable {
+    pub fn new() -> MountTable {
+        MountTable { mounts: HashMap::new() }
+    }
+
+    /// Mounts a responder at the given prefix. The root prefix can be written as `""` or `"/"`.
+    /// Panics if another responder is already mounted at the same prefix.
+    pub fn mount(&mut self, prefix: &str, responder: Box<dyn Responder>) {
+        let prefix = normalize(prefix);
+        if self.mounts.contains_key(&prefix) {
+            panic!("Conflicting mount; a responder is already mounted at {:?}", prefix);
+        }
+        self.mounts.insert(prefix, responder);
+    }
+
+    /// Finds the responder mounted at the longest prefix of `url`'s path, if any.
+    pub fn route(&self, url: &util::UrlParts) -> Option<Mount<'_>> {
+        let path = url.path();
+
+        // Candidate prefixes end at a segment boundary, i.e. just before a / or at the end of path
+        let mut boundaries: Vec<_> = path.match_indices('/').map(|(i, _)| i).collect();
+        boundaries.push(path.len());
+        for &boundary in boundaries.iter().rev() {
+            if let Some((prefix, responder)) = self.mounts.get_key_value(&path[..boundary]) {
+                return Some(Mount {
+                    prefix,
+                    responder: responder.as_ref(),
+                    remainder: util::strip_url_prefix(url, prefix),
+                });
+            }
+        }
+        None
+    }
+}
+
+/// Ensures the prefix has a leading slash and no trailing slash; the root is the empty string.
+fn normalize(prefix: &str) -> String {
+    let trimmed = prefix.trim_matches('/');
+    if trimmed.is_empty() { String::new() } else { format!("/{}", trimmed) }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use responders;
+    use request;
+
+    struct Named(&'static str);
+    impl Responder for Named {
+        fn handle(&self, _request: &request::Request, _url: &util::UrlParts)
+                -> responders::Response {
+            util::ResponseBuilder::ok().text(self.0).build()
+        }
+    }
+
+    fn table(prefixes: &[&'static str]) -> MountTable {
+        let mut table = MountTable::new();
+        for prefix in prefixes {
+            table.mount(prefix, Box::new(Named(prefix)));
+        }
+        table
+    }
+
+    fn routed_prefix(table: &MountTable, url: &str) -> Option<String> {
+        table.route(&util::UrlParts::new(url)).map(|m| m.prefix.to_string())
+    }
+
+    #[test]
+    fn longest_prefix() {
+        let table = table(&["/", "/api", "/api/v2"]);
+        assert_eq!(routed_prefix(&table, "/"), Some("".into()));
+        assert_eq!(routed_prefix(&table, "/foo"), Some("".into()));
+        assert_eq!(routed_prefix(&table, "/api"), Some("/api".into()));
+        assert_eq!(routed_prefix(&table, "/api/v1/foo"), Some("/api".into()));
+        assert_eq!(routed_prefix(&table, "/api/v2"), Some("/api/v2".into()));
+        assert_eq!(routed_prefix(&table, "/api/v2/foo/bar?baz"), Some("/api/v2".into()));
+    }
+
+    #[test]
+    fn whole_segments_only() {
+        let table = table(&["/api/v2"]);
+        assert_eq!(routed_prefix(&table, "/api/v2x"), None);
+        assert_eq!(routed_prefix(&table, "/api"), None);
+        assert_eq!(routed_prefix(&table, "/api/v2?x=1"), Some("/api/v2".into()));
+    }
+
+    #[test]
+    fn remainder() {
+        let table = table(&["/api/v2/"]);
+        let mount = table.route(&util::UrlParts::new("/api/v2/foo/bar?baz=1")).unwrap();
+        assert_eq!(mount.remainder.path, "/foo/bar");
+        assert_eq!(mount.remainder.path_components, vec!["foo", "bar"]);
+        assert_eq!(mount.remainder.query.get("baz").unwrap(), "1");
+        assert_eq!(table.route(&util::UrlParts::new("/api/v2")).unwrap().remainder.path, "/");
+    }
+
+    #[test]
+    #[should_panic(expected = "Conflicting mount")]
+    fn conflicting_mount() {
+        table(&["/foo", "/foo/"]);
+    }
+}
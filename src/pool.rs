@@ -0,0 +1,142 @@
+// Copyright 2017 Google LLC, Matthew Vilim
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//      http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! A fixed-size pool of worker threads pulling requests off a shared `tiny_http::Server`.
+
+use logging;
+use std::sync::{Arc, Condvar, Mutex, Weak};
+use std::thread;
+use std::time::{Duration, Instant};
+use tiny_http;
+
+/// How long a worker blocks waiting for a request before re-checking whether it should stop.
+const POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+pub struct WorkerPool {
+    workers: Vec<thread::JoinHandle<()>>,
+}
+
+impl WorkerPool {
+    /// Spawns `size` threads, each of which receives requests from `server` and passes them to
+    /// `handler` until `stopper` is triggered. `tiny_http` queues requests internally, so a slow
+    /// handler only occupies its own worker. Failures to receive a request are reported to `log`.
+    ///
+    /// Workers only hold a weak reference to the server between requests, so once the caller drops
+    /// its `Arc` the listening socket is closed even if some workers are still busy.
+    pub fn start<F>(size: usize, server: &Arc<tiny_http::Server>, stopper: &Stopper,
+                    log: &Arc<logging::AccessLog>, handler: F)
+        -> WorkerPool where F: Fn(tiny_http::Request) + Send + Sync + 'static
+    {
+        assert!(size > 0, "WorkerPool requires at least one worker");
+        let handler = Arc::new(handler);
+        let workers = (0..size).map(|i| {
+            let server = Arc::downgrade(server);
+            let stopper = stopper.clone();
+            let handler = handler.clone();
+            let log = log.clone();
+            thread::Builder::new()
+                .name(format!("worker-{}", i))
+                .spawn(move || {
+                    while !stopper.is_stopped() {
+                        match receive(&server) {
+                            Some(Ok(Some(request))) => handler(request),
+                            Some(Ok(None)) => {},
+                            Some(Err(e)) => log.error(&format!("failed to receive request: {}", e)),
+                            None => break, // server has been shut down
+                        }
+                    }
+                })
+                .expect("Failed to spawn worker thread")
+        }).collect();
+        WorkerPool { workers }
+    }
+
+    /// Waits up to `timeout` for every worker to finish its current request and stop, returning
+    /// the number of workers that were still busy when the deadline passed.
+    pub fn join_timeout(self, timeout: Duration) -> usize {
+        let deadline = Instant::now() + timeout;
+        while self.workers.iter().any(|w| !w.is_finished()) && Instant::now() < deadline {
+            thread::sleep(Duration::from_millis(10));
+        }
+        let (finished, busy): (Vec<_>, Vec<_>) =
+            self.workers.into_iter().partition(|w| w.is_finished());
+        for worker in finished {
+            // A worker only fails to join if it panicked, which has already been reported
+            let _ = worker.join();
+        }
+        busy.len()
+    }
+}
+
+/// Waits briefly for a request, if the server is still running.
+fn receive(server: &Weak<tiny_http::Server>)
+        -> Option<::std::io::Result<Option<tiny_http::Request>>> {
+    // The upgraded reference is dropped before the request is handled
+    server.upgrade().map(|server| server.recv_timeout(POLL_INTERVAL))
+}
+
+/// Signals a `WorkerPool` to stop; workers finish the request they're handling (if any) and exit.
+#[derive(Clone)]
+pub struct Stopper {
+    stopped: Arc<(Mutex<bool>, Condvar)>,
+}
+
+impl Stopper {
+    pub fn new() -> Stopper {
+        Stopper { stopped: Arc::new((Mutex::new(false), Condvar::new())) }
+    }
+
+    /// Requests a stop, returning false if one had already been requested.
+    pub fn stop(&self) -> bool {
+        let (ref lock, ref condvar) = *self.stopped;
+        let mut stopped = lock.lock().unwrap();
+        let was_running = !*stopped;
+        *stopped = true;
+        condvar.notify_all();
+        was_running
+    }
+
+    pub fn is_stopped(&self) -> bool {
+        *self.stopped.0.lock().unwrap()
+    }
+
+    /// Blocks until a stop is requested.
+    pub fn wait(&self) {
+        let (ref lock, ref condvar) = *self.stopped;
+        let mut stopped = lock.lock().unwrap();
+        while !*stopped {
+            stopped = condvar.wait(stopped).unwrap();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn stopper() {
+        let stopper = Stopper::new();
+        assert!(!stopper.is_stopped());
+
+        let waiter = {
+            let stopper = stopper.clone();
+            thread::spawn(move || stopper.wait())
+        };
+        assert!(stopper.stop());
+        waiter.join().unwrap();
+        assert!(stopper.is_stopped());
+        assert!(!stopper.stop());
+    }
+}
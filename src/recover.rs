@@ -0,0 +1,112 @@
+// Copyright 2017 Google LLC, Matthew Vilim
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//      http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Isolates panics in request handling, so one failing responder doesn't take the server down.
+
+use std::any::Any;
+use std::cell::{Cell, RefCell};
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::Once;
+
+/// Details of a caught panic.
+#[derive(Debug, Clone)]
+pub struct Panic {
+    pub message: String,
+    /// `file:line:column` of the panic, if known
+    pub location: Option<String>,
+}
+
+thread_local! {
+    static CATCHING: Cell<bool> = const { Cell::new(false) };
+    static LAST_PANIC: RefCell<Option<Panic>> = const { RefCell::new(None) };
+}
+
+static INSTALL_HOOK: Once = Once::new();
+
+/// Installs a panic hook that records the message and location of panics raised inside `catch()`
+/// instead of printing them, so the caller can report them along with the request that failed.
+/// Panics anywhere else are passed to the default hook. Safe to call more than once.
+pub fn install_hook() {
+    INSTALL_HOOK.call_once(|| {
+        let default_hook = panic::take_hook();
+        panic::set_hook(Box::new(move |info| {
+            if CATCHING.with(Cell::get) {
+                let panic = Panic {
+                    message: payload_message(info.payload()),
+                    location: info.location().map(|l| l.to_string()),
+                };
+                LAST_PANIC.with(|last| *last.borrow_mut() = Some(panic));
+            } else {
+                default_hook(info);
+            }
+        }));
+    });
+}
+
+/// Runs `f`, converting any panic into an `Err`.
+///
+/// `f` is assumed to be unwind safe; responders are shared between requests, so any state they
+/// mutate must already be guarded (e.g. by a `Mutex`, which is poisoned by a panic).
+pub fn catch<F: FnOnce() -> R, R>(f: F) -> Result<R, Panic> {
+    let was_catching = CATCHING.with(|c| c.replace(true));
+    let result = panic::catch_unwind(AssertUnwindSafe(f));
+    CATCHING.with(|c| c.set(was_catching));
+    result.map_err(|payload| {
+        LAST_PANIC.with(|last| last.borrow_mut().take())
+            // Only happens if install_hook() wasn't called
+            .unwrap_or_else(|| Panic { message: payload_message(&*payload), location: None })
+    })
+}
+
+fn payload_message(payload: &(dyn Any + Send)) -> String {
+    if let Some(message) = payload.downcast_ref::<&str>() {
+        message.to_string()
+    } else if let Some(message) = payload.downcast_ref::<String>() {
+        message.clone()
+    } else {
+        "Box<dyn Any>".into()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn no_panic() {
+        install_hook();
+        assert_eq!(catch(|| 5).unwrap(), 5);
+    }
+
+    #[test]
+    fn caught_panic() {
+        install_hook();
+        let panic = catch(|| -> u32 { panic!("Oh no {}", 5) }).unwrap_err();
+        assert_eq!(panic.message, "Oh no 5");
+        assert!(panic.location.unwrap().starts_with(file!()));
+
+        // The server keeps working afterwards
+        assert_eq!(catch(|| "fine").unwrap(), "fine");
+    }
+
+    #[test]
+    fn nested() {
+        install_hook();
+        let outer = catch(|| {
+            assert!(catch(|| panic!("inner")).is_err());
+            panic!("outer")
+        });
+        assert_eq!(outer.unwrap_err().message, "outer");
+    }
+}
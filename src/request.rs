@@ -0,0 +1,210 @@
+// Copyright 2017 Google LLC, Matthew Vilim
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//      http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! The request as seen by responders - the `tiny_http::Request` along with its buffered body.
+//!
+//! Reading a `tiny_http::Request`'s body requires mutable access, which responders (being shared
+//! between worker threads, and passed the request by reference) don't have. Instead the server
+//! reads the body up front, subject to a size limit, and passes it alongside the request.
+
+use error::RivetError;
+use serde::de::DeserializeOwned;
+use serde_json;
+use serde_urlencoded;
+use std::fmt;
+use std::io::Read;
+use std::str;
+use tiny_http;
+use util;
+
+/// The default for `Config::max_body_size`
+pub const DEFAULT_MAX_BODY_SIZE: usize = 1024 * 1024;
+
+const FORM: &str = "application/x-www-form-urlencoded";
+const JSON: &str = "application/json";
+
+pub struct Request<'a> {
+    inner: &'a tiny_http::Request,
+    body: Body,
+}
+
+impl<'a> Request<'a> {
+    pub fn new(inner: &'a tiny_http::Request, body: Body) -> Request<'a> {
+        Request { inner, body }
+    }
+
+    pub fn method(&self) -> &tiny_http::Method { self.inner.method() }
+
+    /// The request target, as sent by the client.
+    pub fn url(&self) -> &str { self.inner.url() }
+
+    /// Returns the value of the first header with the given (case-insensitive) name, if any.
+    pub fn header(&self, name: &'static str) -> Option<&str> { util::header(self.inner, name) }
+
+    pub fn body(&self) -> &Body { &self.body }
+}
+
+/// A request body, along with its media type.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Body {
+    /// The media type from the `Content-Type` header, lowercased and without parameters
+    content_type: Option<String>,
+    bytes: Vec<u8>,
+}
+
+impl Body {
+    pub fn new(content_type: Option<&str>, bytes: Vec<u8>) -> Body {
+        let content_type = content_type
+            .map(|t| t.split(';').next().unwrap_or("").trim().to_ascii_lowercase())
+            .filter(|t| !t.is_empty());
+        Body { content_type, bytes }
+    }
+
+    /// Reads the body of `request`, failing with a 413 if it's more than `limit` bytes. Bodies
+    /// that declare their length up front are rejected without reading them.
+    pub fn read(request: &mut tiny_http::Request, limit: usize) -> Result<Body, RivetError> {
+        if request.body_length().is_some_and(|length| length > limit) {
+            return Err(RivetError::PayloadTooLarge { limit });
+        }
+        let content_type = util::header(request, "Content-Type").map(String::from);
+        let mut bytes = Vec::new();
+        request.as_reader().take(limit as u64 + 1).read_to_end(&mut bytes)
+            .map_err(|e| RivetError::BadRequest(format!("Failed to read request body: {}", e)))?;
+        if bytes.len() > limit {
+            return Err(RivetError::PayloadTooLarge { limit });
+        }
+        Ok(Body::new(content_type.as_deref(), bytes))
+    }
+
+    pub fn content_type(&self) -> Option<&str> { self.content_type.as_deref() }
+
+    #[allow(dead_code)]
+    pub fn bytes(&self) -> &[u8] { &self.bytes }
+
+    pub fn is_empty(&self) -> bool { self.bytes.is_empty() }
+
+    pub fn is_form(&self) -> bool { self.content_type() == Some(FORM) }
+
+    pub fn is_json(&self) -> bool { self.content_type() == Some(JSON) }
+
+    pub fn text(&self) -> Result<&str, RivetError> {
+        str::from_utf8(&self.bytes)
+            .map_err(|e| RivetError::BadRequest(format!("Request body is not UTF-8: {}", e)))
+    }
+
+    /// The fields of an `application/x-www-form-urlencoded` body. An empty body, of any type,
+    /// has no fields.
+    pub fn form(&self) -> Result<util::Query, RivetError> {
+        if self.is_empty() {
+            return Ok(util::Query::default());
+        }
+        self.expect_type(FORM)?;
+        Ok(util::Query::parse(self.text()?))
+    }
+
+    /// An `application/json` body, as an untyped JSON value.
+    pub fn json(&self) -> Result<serde_json::Value, RivetError> {
+        self.expect_type(JSON)?;
+        self.decode()
+    }
+
+    /// Decodes a form or JSON body, depending on its content type, into a `T`.
+    pub fn decode<T: DeserializeOwned>(&self) -> Result<T, RivetError> {
+        let invalid = |e: &dyn fmt::Display| {
+            RivetError::BadRequest(format!("Invalid {} body: {}", self.content_type().unwrap(), e))
+        };
+        if self.is_form() {
+            serde_urlencoded::from_bytes(&self.bytes).map_err(|e| invalid(&e))
+        } else if self.is_json() {
+            serde_json::from_slice(&self.bytes).map_err(|e| invalid(&e))
+        } else {
+            Err(self.unsupported(&[FORM, JSON]))
+        }
+    }
+
+    fn expect_type(&self, expected: &str) -> Result<(), RivetError> {
+        if self.content_type() == Some(expected) {
+            Ok(())
+        } else {
+            Err(self.unsupported(&[expected]))
+        }
+    }
+
+    fn unsupported(&self, expected: &[&str]) -> RivetError {
+        RivetError::UnsupportedMediaType(format!("Expected a body of type {}, was {}",
+            expected.join(" or "), self.content_type().unwrap_or("unspecified")))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+
+    fn read(body: &'static str, content_type: &str, limit: usize) -> Result<Body, RivetError> {
+        let header = tiny_http::Header::from_bytes(&b"Content-Type"[..], content_type).unwrap();
+        let mut request: tiny_http::Request = tiny_http::TestRequest::new()
+            .with_method(tiny_http::Method::Post).with_header(header).with_body(body).into();
+        Body::read(&mut request, limit)
+    }
+
+    #[derive(Debug, PartialEq, Deserialize)]
+    struct Login {
+        user: String,
+        remember: bool,
+    }
+
+    #[test]
+    fn size_limit() {
+        let body = read("a=1", "application/x-www-form-urlencoded; charset=UTF-8", 3).unwrap();
+        assert_eq!(body.bytes(), b"a=1");
+        assert!(body.is_form());
+        assert_eq!(read("a=12", "text/plain", 3), Err(RivetError::PayloadTooLarge { limit: 3 }));
+    }
+
+    #[test]
+    fn form() {
+        let body = Body::new(Some(FORM), b"user=me%21&tag=a&tag=b+c&remember=true".to_vec());
+        let form = body.form().unwrap();
+        assert_eq!(form.get("user"), Some("me!"));
+        assert_eq!(form.get_all("tag"), vec!["a", "b c"]);
+
+        assert_eq!(Body::new(Some(FORM), b"user=me&remember=true".to_vec()).decode::<Login>(),
+                   Ok(Login { user: "me".into(), remember: true }));
+        assert_eq!(Body::default().form().unwrap().len(), 0);
+    }
+
+    #[test]
+    fn json() {
+        let json = br#"{"user": "me", "remember": false}"#;
+        let body = Body::new(Some("Application/JSON"), json.to_vec());
+        assert_eq!(body.json().unwrap()["user"], "me");
+        assert_eq!(body.decode::<Login>(), Ok(Login { user: "me".into(), remember: false }));
+        let map: HashMap<String, serde_json::Value> = body.decode().unwrap();
+        assert_eq!(map.len(), 2);
+
+        let invalid = Body::new(Some(JSON), b"{\"user\": ".to_vec());
+        assert_eq!(invalid.json().unwrap_err().status_code(), 400);
+    }
+
+    #[test]
+    fn wrong_type() {
+        let body = Body::new(Some("text/plain"), b"hello".to_vec());
+        assert_eq!(body.text(), Ok("hello"));
+        assert_eq!(body.form().unwrap_err().status_code(), 415);
+        assert_eq!(body.json().unwrap_err().status_code(), 415);
+        assert_eq!(body.decode::<Login>().unwrap_err().status_code(), 415);
+        assert_eq!(Body::default().json().unwrap_err().status_code(), 415);
+    }
+}
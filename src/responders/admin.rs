@@ -0,0 +1,73 @@
+// Copyright 2017 Google LLC, Matthew Vilim
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//      http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use pool;
+use error::RivetError;
+use request;
+use responders;
+use util;
+
+/// Admin endpoint that gracefully shuts down the server. It's not part of the `REGISTRY`; instead
+/// it's only mounted (at `/quit`) when an admin token is configured, and requests must present
+/// that token either as a `?token=` parameter or an `Authorization: Bearer` header.
+pub struct Quit {
+    token: String,
+    stopper: pool::Stopper,
+}
+
+impl Quit {
+    pub fn new(token: String, stopper: pool::Stopper) -> Quit {
+        Quit { token, stopper }
+    }
+
+    fn authorized(&self, request: &request::Request, url_parts: &util::UrlParts) -> bool {
+        let bearer = request.header("Authorization")
+            .and_then(|auth| auth.strip_prefix("Bearer "));
+        let param = url_parts.query().get("token");
+        match bearer.or(param) {
+            Some(token) => constant_time_eq(token.as_bytes(), self.token.as_bytes()),
+            None => false,
+        }
+    }
+}
+
+impl responders::Responder for Quit {
+    fn handle(&self, request: &request::Request, url_parts: &util::UrlParts)
+            -> responders::Response {
+        if !self.authorized(request, url_parts) {
+            return Err(RivetError::Forbidden("Forbidden".into()));
+        }
+        self.stopper.stop();
+        util::ResponseBuilder::ok().text("Shutting Down!").build()
+    }
+}
+
+/// Compares two byte strings without short-circuiting, so response times don't leak how much of
+/// the token was guessed correctly.
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0, |acc, (x, y)| acc | (x ^ y)) == 0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn token_comparison() {
+        assert!(constant_time_eq(b"secret", b"secret"));
+        assert!(!constant_time_eq(b"secret", b"secreT"));
+        assert!(!constant_time_eq(b"secret", b"secret2"));
+        assert!(!constant_time_eq(b"", b"secret"));
+    }
+}
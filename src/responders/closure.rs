@@ -12,10 +12,12 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
+use error::RivetError;
+use request;
 use responders;
-use std::collections::HashMap;
-use tiny_http;
+use serde_json;
 use util;
+use util::ResponseBuilder;
 
 /// Use closures to provide dynamic dependencies based on the caller
 /// Inspired by https://github.com/KodrAus/rust-ioc/blob/master/factories
@@ -23,35 +25,45 @@ pub struct Closure {
 }
 
 impl responders::Responder for Closure {
-    fn handle(&self, request: &tiny_http::Request) -> tiny_http::ResponseBox {
-        let url_parts = util::strip_url_prefix(request.url(), "/closure");
-
+    fn handle(&self, request: &request::Request, url_parts: &util::UrlParts)
+            -> responders::Response {
         // This is essentially a manually-written DI pattern - while dense conceptually this function could
         // be generated by a script, macro, codegen, or other tool.
-        let cb: Box<Fn() -> tiny_http::ResponseBox> = match url_parts.path_components().first() {
+        let first = url_parts.path_components().first();
+        let cb: Box<dyn Fn() -> Result<String, RivetError>> = match first {
             Some(path) => match path.as_ref() {
-                "path" => Box::new(|| util::success(&params_only(url_parts.path_components()))),
-                "query" => Box::new(|| util::success(&query_only(url_parts.query()))),
-                "both" => Box::new(|| util::success(&both(url_parts.path_components(), url_parts.query()))),
-                _ => Box::new(|| util::fail404("Not found!"))
+                "path" => Box::new(|| Ok(params_only(url_parts.path_components()))),
+                "query" => Box::new(|| Ok(query_only(url_parts.query()))),
+                "both" => Box::new(|| Ok(both(url_parts.path_components(), url_parts.query()))),
+                "form" => Box::new(|| Ok(form(&request.body().form()?))),
+                "json" => Box::new(|| Ok(json(&request.body().json()?))),
+                _ => Box::new(|| Err(RivetError::NotFound("Not found!".into())))
             },
-            None => Box::new( || util::success(&root()))
+            None => Box::new(|| Ok(root()))
         };
 
-        cb()
+        ResponseBuilder::ok().text(&cb()?).build()
     }
 }
 
-fn root() -> String { "Try /path, /query, or /both".into() }
+fn root() -> String { "Try /path, /query, /both, or POST to /form or /json".into() }
 
 fn params_only(params: &Vec<String>) -> String {
     format!("Params Only! {:?}", params)
 }
 
-fn query_only(query: &HashMap<String, String>) -> String {
+fn query_only(query: &util::Query) -> String {
     format!("Query Only! {:?}", query)
 }
 
-fn both(params: &Vec<String>, query: &HashMap<String, String>) -> String {
+fn both(params: &Vec<String>, query: &util::Query) -> String {
     format!("Params: {:?} and Query: {:?}", params, query)
 }
+
+fn form(form: &util::Query) -> String {
+    format!("Form! {:?}", form)
+}
+
+fn json(json: &serde_json::Value) -> String {
+    format!("JSON! {}", json)
+}
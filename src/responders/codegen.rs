@@ -0,0 +1,138 @@
+// Copyright 2017 Google LLC, Matthew Vilim
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//      http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Handlers declared with `#[route]` attributes, in the style of Rocket. Unlike `traits_macro`
+//! there's no hand-written dispatcher: the attributes generate the routing, path parameter
+//! parsing, and injection of each handler's other parameters.
+
+use error::RivetError;
+use inject::Dep;
+use request;
+use responders;
+use responders::routes::Routes;
+use util;
+
+bindings!(Deps, DepsBuilder {
+    url_parts: UrlParts => util::UrlParts,
+    form_params: FormParams => util::Query,
+});
+provider!(Deps, PathParts, Vec<String>, UrlParts, |d: &'a dyn UrlParts| d.get().path_components());
+provider!(Deps, UrlParams, util::Query, UrlParts, |d: &'a dyn UrlParts| d.get().query());
+
+/// The routes served by the `codegen` responder in the `REGISTRY`.
+pub fn example() -> Box<dyn responders::Responder> {
+    Box::new(Routes::new(deps, routes![index, user, update_user, file]))
+}
+
+fn deps(request: &request::Request, url_parts: &util::UrlParts) -> Result<Deps, RivetError> {
+    // Like `traits_macro`, other bodies bind an empty form rather than failing every route
+    let body = request.body();
+    let form = if body.is_form() { body.form()? } else { util::Query::default() };
+    Ok(DepsBuilder::new()
+        .url_parts(url_parts.clone())
+        .form_params(form)
+        .build())
+}
+
+#[route(GET, "/")]
+fn index() -> &'static str { "Try /users/7?verbose, POST to /users/7, or /files/a/b.txt" }
+
+#[route(GET, "/users/<id>")]
+fn user(id: u64, query: Dep<dyn UrlParams>) -> String {
+    format!("User #{} with query {:?}", id, query)
+}
+
+#[route(POST, PUT, "/users/<id>")]
+fn update_user(id: u64, form: Dep<dyn FormParams>) -> Result<String, RivetError> {
+    match form.get("name") {
+        Some(name) => Ok(format!("Renamed user #{} to {}", id, name)),
+        None => Err(RivetError::BadRequest("Missing name".into())),
+    }
+}
+
+#[route(GET, "/files/<path..>")]
+fn file(path: &str, parts: Dep<dyn PathParts>) -> String {
+    format!("File {} ({} path components)", path, parts.len())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use responders::Responder;
+    use responders::testing;
+    use tiny_http;
+
+    fn call(method: tiny_http::Method, url: &str, form: &str) -> Result<String, RivetError> {
+        call_with(method, url, "application/x-www-form-urlencoded", form)
+    }
+
+    fn call_with(method: tiny_http::Method, url: &str, content_type: &str, body: &str)
+            -> Result<String, RivetError> {
+        let request = tiny_http::TestRequest::new().with_method(method).with_path(url);
+        let body = request::Body::new(Some(content_type), body.as_bytes().to_vec());
+        testing::text(testing::handle(&*example(), request, body))
+    }
+
+    fn get(url: &str) -> Result<String, RivetError> { call(tiny_http::Method::Get, url, "") }
+
+    #[test]
+    fn routes() {
+        assert!(get("/").unwrap().starts_with("Try"));
+        assert_eq!(get("/users/7?verbose").unwrap(), r#"User #7 with query {"verbose": ""}"#);
+        assert_eq!(get("/files/a/b.txt").unwrap(), "File a/b.txt (3 path components)");
+        assert_eq!(call(tiny_http::Method::Post, "/users/7", "name=Bo").unwrap(),
+                   "Renamed user #7 to Bo");
+    }
+
+    #[test]
+    fn errors() {
+        assert_eq!(get("/users/seven").unwrap_err().status_code(), 400);
+        assert_eq!(get("/users/7/more").unwrap_err().status_code(), 404);
+        assert_eq!(get("/files").unwrap_err().status_code(), 404);
+        assert_eq!(call(tiny_http::Method::Delete, "/users/7", "").unwrap_err(),
+                   RivetError::MethodNotAllowed { allowed: vec![
+                       tiny_http::Method::Get, tiny_http::Method::Post, tiny_http::Method::Put,
+                       tiny_http::Method::Head, tiny_http::Method::Options] });
+        assert_eq!(call(tiny_http::Method::Post, "/users/7", "").unwrap_err().status_code(), 400);
+        // Other bodies are only a problem for handlers that use the form
+        let json = |method, url| call_with(method, url, "application/json", "{}");
+        assert!(json(tiny_http::Method::Get, "/").is_ok());
+        assert_eq!(json(tiny_http::Method::Post, "/users/7").unwrap_err().status_code(), 400);
+        assert!(example().diagnostics().is_empty());
+    }
+
+    #[inject]
+    fn describe(parts: Dep<dyn PathParts>, query: Dep<dyn UrlParams>) -> String {
+        format!("{:?} {:?}", parts, query)
+    }
+
+    #[route(GET, "/users/<id>")]
+    fn shadowed(id: String) -> String { id }
+
+    #[test]
+    fn injection() {
+        let deps = DepsBuilder::new()
+            .url_parts(util::UrlParts::new("/a/b?c=d"))
+            .form_params(util::Query::default())
+            .build();
+        assert_eq!(describe(&deps), r#"["a", "b"] {"c": "d"}"#);
+    }
+
+    #[test]
+    fn shadowed_routes() {
+        let routes = Routes::new(deps, routes![user, shadowed, file]);
+        assert_eq!(routes.diagnostics(),
+                   vec!["route GET /users/<id> is unreachable, shadowed by GET /users/<id>"]);
+    }
+}
@@ -12,91 +12,1128 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
+use error::RivetError;
+use request;
 use responders;
-use std::any::Any;
+use std::any::{self, Any, TypeId};
 use std::cell::RefCell;
-use std::collections::HashMap;
-use std::rc::Rc;
-use std::sync::Mutex;
-use tiny_http;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::fmt;
+use std::ops::Deref;
+use std::sync::{Arc, Mutex};
 use util;
 
-struct Container {
-    constructors: HashMap<String, Box<Any>>,
+/// An `Arc<T>` of the binding's type `T`, which may be unsized (e.g. `dyn Storage`)
+type Instance = Arc<dyn Any + Send + Sync>;
+type Constructor = Box<dyn Fn(&Container) -> Result<Instance, ResolveError> + Send + Sync>;
+/// Builds a `Set` or `Map` from the instances created by its contributions
+type Collect = fn(Vec<Instance>) -> Instance;
+
+/// How a binding's instances are created, and how long they're shared for.
+enum Lifetime {
+    /// A value added directly to the container
+    Instance(Instance),
+    /// Created the first time it's resolved, then shared by the container and all its scopes. If
+    /// several threads resolve it at once each may construct it, but only the first is kept.
+    Singleton(Constructor, Mutex<Option<Instance>>),
+    /// Created once per scope (e.g. per request), and dropped along with the scope
+    Scoped(Constructor),
+    /// Created every time it's resolved
+    Transient(Constructor),
+    /// Added to each scope, e.g. details of the request; see `Container::declare()`
+    Declared,
+    /// A `Set` or `Map` that several bindings contribute to, collected every time it's resolved
+    Multi(Vec<Contribution>, Collect),
+}
+
+impl Lifetime {
+    fn is_scoped(&self) -> bool {
+        matches!(*self, Lifetime::Scoped(_) | Lifetime::Declared)
+    }
+}
+
+struct Binding {
+    type_id: TypeId,
+    type_name: &'static str,
+    lifetime: Lifetime,
+    /// The bindings its constructor resolves, as declared with `Needs::needs()` or inferred from
+    /// the parameters of an injected constructor
+    needs: Vec<Dependency>,
+}
+
+impl Binding {
+    fn new<T: ?Sized + 'static>(lifetime: Lifetime) -> Binding {
+        Binding {
+            type_id: TypeId::of::<T>(),
+            type_name: any::type_name::<T>(),
+            lifetime,
+            needs: Vec::new(),
+        }
+    }
+}
+
+struct Dependency {
+    /// The name of the binding, or `None` if it's resolved by type
+    name: Option<String>,
+    type_id: TypeId,
+    type_name: &'static str,
+}
+
+impl Dependency {
+    fn new<T: ?Sized + 'static>(name: Option<&str>) -> Dependency {
+        Dependency {
+            name: name.map(String::from),
+            type_id: TypeId::of::<T>(),
+            type_name: any::type_name::<T>(),
+        }
+    }
+}
+
+/// An item contributed to a `Set` or `Map`.
+struct Contribution {
+    /// The item's key in a `Map`, formatted with `Debug`, to catch conflicting contributions
+    key: Option<String>,
+    /// Creates an `Arc<T>` of a `Set<T>`'s item, or a `(K, Arc<T>)` entry of a `Map<K, T>`
+    constructor: Constructor,
+}
+
+/// The items contributed to a multibinding with `Container::add_to_set()`, e.g. a list of
+/// middleware. The outermost container's items come first, each in the order they were added.
+struct Set<T: ?Sized>(Vec<Arc<T>>);
+
+impl<T: ?Sized> Deref for Set<T> {
+    type Target = [Arc<T>];
+
+    fn deref(&self) -> &[Arc<T>] { &self.0 }
+}
+
+/// The entries contributed to a multibinding with `Container::add_to_map()`, e.g. named health
+/// checks. A scope's entries replace its ancestors' entries with the same key.
+struct Map<K, T: ?Sized>(BTreeMap<K, Arc<T>>);
+
+impl<K, T: ?Sized> Deref for Map<K, T> {
+    type Target = BTreeMap<K, Arc<T>>;
+
+    fn deref(&self) -> &BTreeMap<K, Arc<T>> { &self.0 }
+}
+
+/// Declares the dependencies of a newly added binding, so that `Container::verify()` can check
+/// them up front, e.g. `c.scoped("visit", ..).needs::<Mutex<i32>>("count")`.
+struct Needs<'a> {
+    binding: &'a mut Binding,
 }
 
-impl Container {
-    fn new() -> Container {
-        Container {
-            constructors: HashMap::new(),
+impl<'a> Needs<'a> {
+    fn needs<T: ?Sized + 'static>(self, name: &str) -> Needs<'a> {
+        self.binding.needs.push(Dependency::new::<T>(Some(name)));
+        self
+    }
+}
+
+/// Lets a binding of a concrete type be resolved as the trait object `I`; see `Container::bind()`.
+trait Implements<I: ?Sized> {
+    fn upcast(this: Arc<Self>) -> Arc<I>;
+}
+
+/// Implements `Implements<dyn Trait>` for every implementation of a trait, so that the trait can
+/// be bound to its implementations with `Container::bind()`. The trait must extend `Send + Sync`.
+macro_rules! interface {
+    ($name:ident) => {
+        impl<T: $name + 'static> Implements<dyn $name> for T {
+            fn upcast(this: Arc<T>) -> Arc<dyn $name> { this }
+        }
+    }
+}
+
+/// A constructor whose parameters (up to 12) are all resolved from the container by type, e.g.
+/// `|db: Arc<Db>, config: Arc<Config>| Service::new(db, config)`. Like `inject::Injectable`, but
+/// for the container rather than a binder.
+trait Inject<Args> {
+    type Output;
+
+    /// The constructor's parameters, as dependencies for `Container::verify()`
+    fn dependencies() -> Vec<Dependency>;
+
+    fn construct(&self, container: &Container) -> Result<Self::Output, ResolveError>;
+}
+
+macro_rules! container_inject {
+    ($($arg:ident),*) => {
+        impl<Func, Out, $($arg: ?Sized + Send + Sync + 'static),*> Inject<fn($(&$arg),*) -> Out>
+                for Func where Func: Fn($(Arc<$arg>),*) -> Out {
+            type Output = Out;
+
+            fn dependencies() -> Vec<Dependency> { vec![$(Dependency::new::<$arg>(None)),*] }
+
+            #[allow(unused_variables)]
+            fn construct(&self, container: &Container) -> Result<Out, ResolveError> {
+                Ok(self($(container.try_resolve_type::<$arg>()?),*))
+            }
+        }
+    }
+}
+
+for_each_arity!(container_inject);
+
+/// Why a binding couldn't be resolved, or a problem `Container::verify()` found.
+#[derive(Debug, Clone, PartialEq)]
+enum ResolveError {
+    Missing { name: String, requested: &'static str, available: Vec<String> },
+    WrongType { name: String, requested: &'static str, stored: &'static str },
+    /// Nothing is bound to the type an injected constructor takes
+    NoneOfType { requested: &'static str },
+    /// Several bindings have the type an injected constructor takes, so it's unclear which to use
+    Ambiguous { requested: &'static str, names: Vec<String> },
+    /// A scoped binding was resolved outside of a scope
+    OutOfScope { name: String },
+    /// A declared binding wasn't added to the scope it was resolved from
+    NotAdded { name: String },
+    /// A singleton depends on a scoped binding, which would outlive its scope
+    Captive { singleton: String, scoped: String },
+    /// The chain of bindings that depend on each other, starting and ending with the same one
+    Cycle(Vec<String>),
+    /// A problem with one of `binding`'s declared dependencies
+    Dependency { binding: String, cause: Box<ResolveError> },
+}
+
+impl fmt::Display for ResolveError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            ResolveError::Missing { ref name, requested, ref available } =>
+                write!(f, "No binding named {:?} (requested as {}); available bindings are: {}",
+                       name, requested, available.join(", ")),
+            ResolveError::WrongType { ref name, requested, stored } =>
+                write!(f, "{:?} was requested as {}, but is bound to {}", name, requested, stored),
+            ResolveError::NoneOfType { requested } => write!(f, "No binding of type {}", requested),
+            ResolveError::Ambiguous { requested, ref names } =>
+                write!(f, "Several bindings of type {}: {}", requested, names.join(", ")),
+            ResolveError::OutOfScope { ref name } =>
+                write!(f, "{:?} is scoped, and can only be resolved within a scope()", name),
+            ResolveError::NotAdded { ref name } =>
+                write!(f, "{:?} is declared, but wasn't added to this scope", name),
+            ResolveError::Captive { ref singleton, ref scoped } =>
+                write!(f, "singleton {:?} can't depend on {:?}, which is scoped",
+                       singleton, scoped),
+            ResolveError::Cycle(ref names) =>
+                write!(f, "dependency cycle: {}", names.join(" -> ")),
+            ResolveError::Dependency { ref binding, ref cause } =>
+                write!(f, "{:?} depends on a missing or invalid binding: {}", binding, cause),
+        }
+    }
+}
+
+impl From<ResolveError> for RivetError {
+    fn from(error: ResolveError) -> RivetError {
+        RivetError::Internal(format!("Failed to resolve dependencies: {}", error))
+    }
+}
+
+thread_local! {
+    /// The bindings being constructed on this thread, innermost last, to detect cycles
+    static CONSTRUCTING: RefCell<Vec<String>> = const { RefCell::new(Vec::new()) };
+}
+
+/// Calls `construct` to create an instance of the binding `name`, failing instead if that
+/// binding is already being constructed, since that would recurse forever (or deadlock).
+fn constructing<F>(name: &str, construct: F) -> Result<Instance, ResolveError>
+        where F: FnOnce() -> Result<Instance, ResolveError> {
+    /// Pops the binding even if its constructor panics
+    struct Guard;
+    impl Drop for Guard {
+        fn drop(&mut self) { CONSTRUCTING.with(|stack| stack.borrow_mut().pop()); }
+    }
+
+    CONSTRUCTING.with(|stack| {
+        let mut stack = stack.borrow_mut();
+        if let Some(start) = stack.iter().position(|n| n == name) {
+            let mut chain = stack[start..].to_vec();
+            chain.push(name.to_string());
+            return Err(ResolveError::Cycle(chain));
+        }
+        stack.push(name.to_string());
+        Ok(())
+    })?;
+    let _guard = Guard;
+    construct()
+}
+
+/// A string-keyed DI container. The server creates one for the app, and a child `scope()` of it
+/// for each request; names are resolved from the innermost container that binds them.
+///
+/// Constructors are passed the container to resolve their own dependencies from - for singletons
+/// that's the container they're bound in, so they can't depend on anything scoped - and return
+/// any `try_resolve()` error, which fails the request rather than panicking. Declaring those
+/// dependencies lets `verify()` catch missing bindings, mismatched types and cycles at startup,
+/// rather than when a request first needs them.
+///
+/// Alternatively the `add_*factory()` methods bind a constructor whose parameters are injected by
+/// type, with its dependencies declared automatically. These bindings are named after their type,
+/// and each parameter is resolved from the innermost container with a binding of that type.
+///
+/// Multibindings collect items from several bindings into one: each part of the app can
+/// `add_to_set()` or `add_to_map()`, and constructors that take an `Arc<Set<T>>` or
+/// `Arc<Map<K, T>>` are injected with everything contributed by the container and its ancestors.
+struct Container<'p> {
+    parent: Option<&'p Container<'p>>,
+    bindings: HashMap<String, Binding>,
+    /// The instances of scoped bindings created for this scope
+    scoped: Mutex<HashMap<String, Instance>>,
+}
+
+// Not all of the container's API is used by the `Factory` responder; the rest is only tested
+impl<'p> Container<'p> {
+    fn new() -> Container<'static> {
+        Container { parent: None, bindings: HashMap::new(), scoped: Mutex::new(HashMap::new()) }
+    }
+
+    /// A child container, whose own bindings (and scoped instances) take precedence over - and
+    /// don't outlive - this one's.
+    fn scope(&self) -> Container<'_> {
+        Container { parent: Some(self), ..Container::new() }
+    }
+
+    fn add<T: Any + Send + Sync>(&mut self, name: &str, value: T) -> Needs<'_> {
+        self.insert(name, Binding::new::<T>(Lifetime::Instance(Arc::new(Arc::new(value)))))
+    }
+
+    fn singleton<T, F>(&mut self, name: &str, constructor: F) -> Needs<'_>
+            where T: Any + Send + Sync,
+                  F: Fn(&Container) -> Result<T, ResolveError> + Send + Sync + 'static {
+        let lifetime = Lifetime::Singleton(boxed(constructor), Mutex::new(None));
+        self.insert(name, Binding::new::<T>(lifetime))
+    }
+
+    fn scoped<T, F>(&mut self, name: &str, constructor: F) -> Needs<'_>
+            where T: Any + Send + Sync,
+                  F: Fn(&Container) -> Result<T, ResolveError> + Send + Sync + 'static {
+        self.insert(name, Binding::new::<T>(Lifetime::Scoped(boxed(constructor))))
+    }
+
+    #[cfg_attr(not(test), allow(dead_code))]
+    fn transient<T, F>(&mut self, name: &str, constructor: F) -> Needs<'_>
+            where T: Any + Send + Sync,
+                  F: Fn(&Container) -> Result<T, ResolveError> + Send + Sync + 'static {
+        self.insert(name, Binding::new::<T>(Lifetime::Transient(boxed(constructor))))
+    }
+
+    /// Binds a singleton created by an injected constructor, e.g.
+    /// `c.add_factory(|db: Arc<Db>, config: Arc<Config>| Service::new(db, config))`.
+    fn add_factory<Args, F>(&mut self, constructor: F)
+            where F: Inject<Args> + Send + Sync + 'static, F::Output: Any + Send + Sync {
+        let lifetime = Lifetime::Singleton(injected(constructor), Mutex::new(None));
+        self.bind_injected::<Args, F>(lifetime);
+    }
+
+    /// Binds a scoped instance created by an injected constructor.
+    #[cfg_attr(not(test), allow(dead_code))]
+    fn add_scoped_factory<Args, F>(&mut self, constructor: F)
+            where F: Inject<Args> + Send + Sync + 'static, F::Output: Any + Send + Sync {
+        self.bind_injected::<Args, F>(Lifetime::Scoped(injected(constructor)));
+    }
+
+    /// Binds a transient instance created by an injected constructor.
+    #[cfg_attr(not(test), allow(dead_code))]
+    fn add_transient_factory<Args, F>(&mut self, constructor: F)
+            where F: Inject<Args> + Send + Sync + 'static, F::Output: Any + Send + Sync {
+        self.bind_injected::<Args, F>(Lifetime::Transient(injected(constructor)));
+    }
+
+    /// Declares a binding that each scope `add()`s itself, so other bindings can depend on it.
+    fn declare<T: Any>(&mut self, name: &str) {
+        self.insert(name, Binding::new::<T>(Lifetime::Declared));
+    }
+
+    /// Binds the trait object `I` to its implementation `Impl`, which must be bound separately,
+    /// e.g. `c.bind::<dyn Storage, PostgresStorage>()`. Resolving `I` resolves `Impl`, so its
+    /// instances live as long as `Impl`'s do. `I` must have been declared with `interface!`.
+    fn bind<I, Impl>(&mut self)
+            where I: ?Sized + Send + Sync + 'static, Impl: Implements<I> + Send + Sync + 'static {
+        self.bind_named::<I, Impl>(any::type_name::<I>());
+    }
+
+    /// Like `bind()`, but under a qualifier, so several implementations of the same trait can be
+    /// bound; e.g. after `c.bind_named::<dyn Storage, Memory>("cache")`,
+    /// `c.try_resolve::<dyn Storage>("cache")` resolves `Memory`. Constructors injected with an
+    /// `Arc<I>` get the implementation bound with `bind()`.
+    fn bind_named<I, Impl>(&mut self, qualifier: &str)
+            where I: ?Sized + Send + Sync + 'static, Impl: Implements<I> + Send + Sync + 'static {
+        let constructor = Box::new(|container: &Container| {
+            let implementation = container.try_resolve_type::<Impl>()?;
+            Ok(Arc::new(Impl::upcast(implementation)) as Instance)
+        });
+        let mut binding = Binding::new::<I>(Lifetime::Transient(constructor));
+        binding.needs.push(Dependency::new::<Impl>(None));
+        self.insert(qualifier, binding);
+    }
+
+    /// Contributes an item to the `Set<T>` multibinding, e.g.
+    /// `c.add_to_set(Arc::new(Gzip) as Arc<dyn Middleware>)`.
+    fn add_to_set<T: ?Sized + Send + Sync + 'static>(&mut self, item: Arc<T>) {
+        let constructor = Box::new(move |_: &Container| Ok(Arc::new(item.clone()) as Instance));
+        self.contribute::<Set<T>>(None, constructor, Vec::new(), collect_set::<T>);
+    }
+
+    /// Contributes an item created by an injected constructor to the `Set<T>` multibinding; it's
+    /// created each time the set is resolved.
+    #[cfg_attr(not(test), allow(dead_code))]
+    fn add_set_factory<T, Args, F>(&mut self, constructor: F)
+            where T: ?Sized + Send + Sync + 'static,
+                  F: Inject<Args, Output = Arc<T>> + Send + Sync + 'static {
+        let constructor = Box::new(move |container: &Container| {
+            constructor.construct(container).map(|item| Arc::new(item) as Instance)
+        });
+        self.contribute::<Set<T>>(None, constructor, F::dependencies(), collect_set::<T>);
+    }
+
+    /// Contributes an entry to the `Map<K, T>` multibinding, e.g.
+    /// `c.add_to_map("db", Arc::new(DbCheck) as Arc<dyn HealthCheck>)`. Panics if this container
+    /// already contributed an entry with the same key.
+    #[cfg_attr(not(test), allow(dead_code))]
+    fn add_to_map<K, T>(&mut self, key: K, item: Arc<T>)
+            where K: Ord + Clone + fmt::Debug + Send + Sync + 'static,
+                  T: ?Sized + Send + Sync + 'static {
+        let id = format!("{:?}", key);
+        let entry = Arc::new((key, item));
+        let constructor = Box::new(move |_: &Container| Ok(entry.clone() as Instance));
+        self.contribute::<Map<K, T>>(Some(id), constructor, Vec::new(), collect_map::<K, T>);
+    }
+
+    /// Contributes an entry created by an injected constructor to the `Map<K, T>` multibinding;
+    /// it's created each time the map is resolved.
+    fn add_map_factory<K, T, Args, F>(&mut self, key: K, constructor: F)
+            where K: Ord + Clone + fmt::Debug + Send + Sync + 'static,
+                  T: ?Sized + Send + Sync + 'static,
+                  F: Inject<Args, Output = Arc<T>> + Send + Sync + 'static {
+        let id = format!("{:?}", key);
+        let constructor = Box::new(move |container: &Container| {
+            let item = constructor.construct(container)?;
+            Ok(Arc::new((key.clone(), item)) as Instance)
+        });
+        self.contribute::<Map<K, T>>(Some(id), constructor, F::dependencies(), collect_map::<K, T>);
+    }
+
+    /// Adds a contribution to the multibinding of type `C`, which is named after its type.
+    fn contribute<C: ?Sized + 'static>(&mut self, key: Option<String>, constructor: Constructor,
+                                       needs: Vec<Dependency>, collect: Collect) {
+        let name = any::type_name::<C>();
+        let binding = self.bindings.entry(name.to_string())
+            .or_insert_with(|| Binding::new::<C>(Lifetime::Multi(Vec::new(), collect)));
+        match binding.lifetime {
+            Lifetime::Multi(ref mut contributions, _) => {
+                if key.is_some() && contributions.iter().any(|c| c.key == key) {
+                    panic!("Conflicting contribution to {}; {} is already contributed",
+                           name, key.unwrap_or_default());
+                }
+                contributions.push(Contribution { key, constructor });
+            },
+            _ => panic!("{} is already bound, and can't be contributed to", name),
+        }
+        binding.needs.extend(needs);
+    }
+
+    fn insert(&mut self, name: &str, binding: Binding) -> Needs<'_> {
+        self.bindings.insert(name.to_string(), binding);
+        Needs { binding: self.bindings.get_mut(name).expect("Just inserted") }
+    }
+
+    fn bind_injected<Args, F>(&mut self, lifetime: Lifetime)
+            where F: Inject<Args>, F::Output: Any {
+        let mut binding = Binding::new::<F::Output>(lifetime);
+        binding.needs = F::dependencies();
+        self.insert(any::type_name::<F::Output>(), binding);
+    }
+
+    /// Resolves a binding, panicking if it can't be resolved.
+    #[cfg(test)]
+    fn resolve<T: ?Sized + Send + Sync + 'static>(&self, name: &str) -> Arc<T> {
+        self.try_resolve(name).unwrap_or_else(|e| panic!("{}", e))
+    }
+
+    fn try_resolve<T: ?Sized + Send + Sync + 'static>(&self, name: &str)
+            -> Result<Arc<T>, ResolveError> {
+        let (container, binding) = self.find(name)
+            .ok_or_else(|| self.missing(name, any::type_name::<T>()))?;
+        if binding.type_id != TypeId::of::<T>() {
+            return Err(ResolveError::WrongType {
+                name: name.to_string(), requested: any::type_name::<T>(), stored: binding.type_name,
+            });
+        }
+        let instance = match binding.lifetime {
+            Lifetime::Instance(ref value) => value.clone(),
+            Lifetime::Singleton(ref constructor, ref instance) => {
+                let existing = instance.lock().unwrap_or_else(|e| e.into_inner()).clone();
+                match existing {
+                    Some(existing) => existing,
+                    None => {
+                        // Not holding the lock, since a constructor that (indirectly) resolves
+                        // this binding on another thread would otherwise deadlock rather than
+                        // being reported as a cycle
+                        let constructed = constructing(name, || constructor(container))?;
+                        instance.lock().unwrap_or_else(|e| e.into_inner())
+                            .get_or_insert(constructed).clone()
+                    },
+                }
+            },
+            Lifetime::Scoped(ref constructor) => self.scoped_instance(name, constructor)?,
+            Lifetime::Transient(ref constructor) => constructing(name, || constructor(self))?,
+            Lifetime::Declared => return Err(ResolveError::NotAdded { name: name.to_string() }),
+            Lifetime::Multi(_, collect) => {
+                let mut items = Vec::new();
+                self.contributions(name, self, &mut items)?;
+                collect(items)
+            },
+        };
+        let instance = instance.downcast::<Arc<T>>()
+            .expect("Bindings are checked to be of the requested type");
+        Ok(Arc::clone(&instance))
+    }
+
+    /// Resolves the only binding of type `T`, as injected constructors' parameters are.
+    fn try_resolve_type<T: ?Sized + Send + Sync + 'static>(&self) -> Result<Arc<T>, ResolveError> {
+        let name = self.find_type(TypeId::of::<T>(), any::type_name::<T>())?;
+        self.try_resolve(&name)
+    }
+
+    /// Like `try_resolve()`, but a missing binding resolves to `None` rather than an error.
+    #[cfg_attr(not(test), allow(dead_code))]
+    fn resolve_optional<T: ?Sized + Send + Sync + 'static>(&self, name: &str)
+            -> Result<Option<Arc<T>>, ResolveError> {
+        match self.try_resolve(name) {
+            Ok(instance) => Ok(Some(instance)),
+            Err(ResolveError::Missing { .. }) => Ok(None),
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Finds the binding for `name` in this container or its ancestors, along with the container
+    /// it was found in.
+    fn find(&self, name: &str) -> Option<(&Container<'_>, &Binding)> {
+        match self.bindings.get(name) {
+            Some(binding) => Some((self, binding)),
+            None => self.parent.and_then(|parent| parent.find(name)),
+        }
+    }
+
+    /// Finds the name of the only binding of the given type in the innermost container that has
+    /// any. If there are several, the one named after the type (e.g. bound with `bind()` rather
+    /// than `bind_named()`) is the default.
+    fn find_type(&self, type_id: TypeId, type_name: &'static str) -> Result<String, ResolveError> {
+        let mut names: Vec<_> = self.bindings.iter()
+            .filter(|&(_, binding)| binding.type_id == type_id)
+            .map(|(name, _)| name.clone())
+            .collect();
+        names.sort();
+        match names.len() {
+            0 => match self.parent {
+                Some(parent) => parent.find_type(type_id, type_name),
+                None => Err(ResolveError::NoneOfType { requested: type_name }),
+            },
+            1 => Ok(names.remove(0)),
+            _ if names.iter().any(|name| name == type_name) => Ok(type_name.to_string()),
+            _ => Err(ResolveError::Ambiguous { requested: type_name, names }),
+        }
+    }
+
+    /// Creates the items contributed to the multibinding `name` by this container and its
+    /// ancestors, outermost first, resolving their dependencies from `scope`.
+    fn contributions(&self, name: &str, scope: &Container, items: &mut Vec<Instance>)
+            -> Result<(), ResolveError> {
+        if let Some(parent) = self.parent {
+            parent.contributions(name, scope, items)?;
+        }
+        if let Some(&Binding { lifetime: Lifetime::Multi(ref contributions, _), .. }) =
+                self.bindings.get(name) {
+            for contribution in contributions {
+                items.push(constructing(name, || (contribution.constructor)(scope))?);
+            }
+        }
+        Ok(())
+    }
+
+    fn missing(&self, name: &str, requested: &'static str) -> ResolveError {
+        let mut available = Vec::new();
+        let mut container = Some(self);
+        while let Some(c) = container {
+            available.extend(c.bindings.keys().cloned());
+            container = c.parent;
+        }
+        available.sort();
+        available.dedup();
+        ResolveError::Missing { name: name.to_string(), requested, available }
+    }
+
+    fn scoped_instance(&self, name: &str, constructor: &Constructor)
+            -> Result<Instance, ResolveError> {
+        if self.parent.is_none() {
+            return Err(ResolveError::OutOfScope { name: name.to_string() });
+        }
+        if let Some(instance) = self.scoped.lock().unwrap().get(name) {
+            return Ok(instance.clone());
+        }
+        // Not holding the lock, in case the constructor resolves other scoped bindings
+        let instance = constructing(name, || constructor(self))?;
+        Ok(self.scoped.lock().unwrap().entry(name.to_string()).or_insert(instance).clone())
+    }
+
+    /// The name of the binding `dependency` refers to, checking it has the expected type.
+    fn find_dependency(&self, dependency: &Dependency) -> Result<String, ResolveError> {
+        let name = match dependency.name {
+            Some(ref name) => name.clone(),
+            None => return self.find_type(dependency.type_id, dependency.type_name),
+        };
+        match self.find(&name) {
+            None => Err(self.missing(&name, dependency.type_name)),
+            Some((_, found)) if found.type_id != dependency.type_id =>
+                Err(ResolveError::WrongType {
+                    name, requested: dependency.type_name, stored: found.type_name }),
+            Some(_) => Ok(name),
+        }
+    }
+
+    /// Checks the declared dependencies of this container's bindings: that they're bound with the
+    /// expected type, that singletons don't depend on scoped bindings, and that there are no
+    /// cycles. Returns every problem found.
+    fn verify(&self) -> Vec<ResolveError> {
+        let mut names: Vec<_> = self.bindings.keys().collect();
+        names.sort();
+        let mut problems = Vec::new();
+        for name in &names {
+            let binding = &self.bindings[*name];
+            for dependency in &binding.needs {
+                match self.find_dependency(dependency) {
+                    Ok(found) => {
+                        let singleton = matches!(binding.lifetime, Lifetime::Singleton(..));
+                        if singleton && self.is_scoped(&found, &mut HashSet::new()) {
+                            problems.push(ResolveError::Captive {
+                                singleton: name.to_string(), scoped: found });
+                        }
+                    },
+                    Err(e) => problems.push(ResolveError::Dependency {
+                        binding: name.to_string(), cause: Box::new(e) }),
+                }
+            }
+        }
+        let mut done = HashSet::new();
+        for name in names {
+            self.find_cycles(name, &mut Vec::new(), &mut done, &mut problems);
+        }
+        problems
+    }
+
+    /// Whether resolving `name` creates a scoped instance: either it's scoped itself, or it's
+    /// created each time it's resolved (e.g. a trait bound with `bind()`) from something scoped.
+    fn is_scoped(&self, name: &str, seen: &mut HashSet<String>) -> bool {
+        if !seen.insert(name.to_string()) {
+            return false;
+        }
+        match self.find(name) {
+            Some((_, binding)) => match binding.lifetime {
+                Lifetime::Transient(_) | Lifetime::Multi(..) => binding.needs.iter()
+                    .filter_map(|d| self.find_dependency(d).ok())
+                    .any(|dependency| self.is_scoped(&dependency, seen)),
+                ref lifetime => lifetime.is_scoped(),
+            },
+            None => false,
         }
     }
-    
-    fn add<T: Constructors<T> + 'static>(&mut self, s: &str, value: T) {
-        self.constructors.insert(s.to_string(), Box::new(value.construct()) as Box<Any>);
+
+    /// Depth-first search for cycles through `name`'s dependencies, not revisiting bindings in
+    /// `done`.
+    fn find_cycles(&self, name: &str, path: &mut Vec<String>, done: &mut HashSet<String>,
+                   cycles: &mut Vec<ResolveError>) {
+        if let Some(start) = path.iter().position(|n| n == name) {
+            let mut cycle = path[start..].to_vec();
+            cycle.push(name.to_string());
+            cycles.push(ResolveError::Cycle(cycle));
+            return;
+        }
+        if done.contains(name) {
+            return;
+        }
+        if let Some((_, binding)) = self.find(name) {
+            path.push(name.to_string());
+            for dependency in binding.needs.iter().filter_map(|d| self.find_dependency(d).ok()) {
+                self.find_cycles(&dependency, path, done, cycles);
+            }
+            path.pop();
+        }
+        done.insert(name.to_string());
     }
-    
-    fn resolve<T: Clone + 'static>(&self, s: &str) -> T {
-        let item = self.constructors.get(s).unwrap();
-        let construct = item.downcast_ref::<Construct<T>>().unwrap();
-        construct.c()
+}
+
+fn boxed<T, F>(constructor: F) -> Constructor
+        where T: Any + Send + Sync,
+              F: Fn(&Container) -> Result<T, ResolveError> + Send + Sync + 'static {
+    Box::new(move |container: &Container| {
+        constructor(container).map(|value| Arc::new(Arc::new(value)) as Instance)
+    })
+}
+
+fn injected<Args, F>(constructor: F) -> Constructor
+        where F: Inject<Args> + Send + Sync + 'static, F::Output: Any + Send + Sync {
+    Box::new(move |container: &Container| {
+        constructor.construct(container).map(|value| Arc::new(Arc::new(value)) as Instance)
+    })
+}
+
+fn collect_set<T: ?Sized + Send + Sync + 'static>(items: Vec<Instance>) -> Instance {
+    let items = items.into_iter()
+        .map(|item| Arc::clone(&*item.downcast::<Arc<T>>().expect("Contributed as an Arc<T>")))
+        .collect();
+    Arc::new(Arc::new(Set::<T>(items))) as Instance
+}
+
+fn collect_map<K, T>(items: Vec<Instance>) -> Instance
+        where K: Ord + Clone + Send + Sync + 'static, T: ?Sized + Send + Sync + 'static {
+    let mut map = BTreeMap::new();
+    for item in items {
+        let entry = item.downcast::<(K, Arc<T>)>().expect("Contributed as a (K, Arc<T>)");
+        // Later entries are from inner scopes, so they replace their ancestors'
+        map.insert(entry.0.clone(), entry.1.clone());
     }
+    Arc::new(Arc::new(Map::<K, T>(map))) as Instance
+}
+
+/// Counts requests; shared between worker threads, so it needs to be thread-safe.
+#[derive(Default)]
+struct Counter(Mutex<i32>);
+
+/// What the responder knows about the current request; one is created per request.
+#[derive(Serialize)]
+struct Visit {
+    number: i32,
+    path: String,
 }
 
-struct Construct<'a, T> {
-    build: Box<Builder<T> + 'a>,
+/// A strategy for rendering the response, chosen with the `format` query parameter.
+trait Render: Send + Sync {
+    fn render(&self, visit: &Visit) -> util::ResponseBuilder;
 }
+interface!(Render);
 
-impl<'a, T> Construct<'a, T> {
-    fn c(&self) -> T {
-        self.build.c()
+struct Text;
+impl Render for Text {
+    fn render(&self, visit: &Visit) -> util::ResponseBuilder {
+        util::ResponseBuilder::ok().text(&format!("Count {}\nPath: {}", visit.number, visit.path))
     }
 }
 
-trait Constructors<T> {
-    fn construct<'a>(self) -> Construct<'a, T>;
+struct Json;
+impl Render for Json {
+    fn render(&self, visit: &Visit) -> util::ResponseBuilder {
+        util::ResponseBuilder::ok().json(visit)
+    }
+}
+
+/// Adjusts every response; contributed to a `Set<dyn Middleware>`.
+trait Middleware: Send + Sync {
+    fn apply(&self, visit: &Visit, response: util::ResponseBuilder) -> util::ResponseBuilder;
+}
+
+struct NoStore;
+impl Middleware for NoStore {
+    fn apply(&self, _visit: &Visit, response: util::ResponseBuilder) -> util::ResponseBuilder {
+        response.cache_control(util::CacheControl::NoStore)
+    }
 }
 
-impl<T: Clone + 'static> Constructors<T> for T {
-    fn construct<'a>(self) -> Construct<'a, T> {
-        Construct { build: Box::new(self) }
+struct VisitHeader;
+impl Middleware for VisitHeader {
+    fn apply(&self, visit: &Visit, response: util::ResponseBuilder) -> util::ResponseBuilder {
+        response.header("X-Visit", &visit.number.to_string())
     }
 }
 
-trait Builder<T> {
-    fn c(&self) -> T;
+fn middleware(c: &mut Container) {
+    c.add_to_set(Arc::new(NoStore) as Arc<dyn Middleware>);
+    c.add_to_set(Arc::new(VisitHeader) as Arc<dyn Middleware>);
+}
+
+/// Reported by `/health`; contributed to a `Map<&'static str, dyn HealthCheck>`.
+trait HealthCheck: Send + Sync {
+    fn check(&self) -> Result<(), String>;
 }
 
-impl<T: Clone> Builder<T> for T {
-    fn c(&self) -> T {
-        self.clone()
+struct CounterCheck(Arc<Counter>);
+impl HealthCheck for CounterCheck {
+    fn check(&self) -> Result<(), String> {
+        if (self.0).0.is_poisoned() { Err("lock is poisoned".into()) } else { Ok(()) }
     }
 }
 
+struct RenderCheck(Arc<dyn Render>);
+impl HealthCheck for RenderCheck {
+    fn check(&self) -> Result<(), String> {
+        let visit = Visit { number: 0, path: "/".into() };
+        self.0.render(&visit).build().map(|_| ()).map_err(|e| e.to_string())
+    }
+}
+
+fn health_checks(c: &mut Container) {
+    c.add_map_factory("counter", |counter: Arc<Counter>| {
+        Arc::new(CounterCheck(counter)) as Arc<dyn HealthCheck>
+    });
+    c.add_map_factory("render", |render: Arc<dyn Render>| {
+        Arc::new(RenderCheck(render)) as Arc<dyn HealthCheck>
+    });
+}
+
+/// Lists the result of each check, failing with a 503 if any of them do.
+fn health(checks: &Map<&'static str, dyn HealthCheck>) -> responders::Response {
+    let results: Vec<_> = checks.iter().map(|(name, check)| (name, check.check())).collect();
+    let status = if results.iter().all(|(_, result)| result.is_ok()) { 200 } else { 503 };
+    let lines: Vec<_> = results.iter().map(|(name, result)| match *result {
+        Ok(()) => format!("{}: ok", name),
+        Err(ref e) => format!("{}: {}", name, e),
+    }).collect();
+    util::ResponseBuilder::new(status).text(&lines.join("\n")).build()
+}
+
 pub struct Factory {
   // Global state in the responder - generally not a good practice but used here as an example of
   // a persistent resource.
-  container: Mutex<Container>,
+  container: Container<'static>,
 }
 
 impl Factory {
     pub fn new() -> Factory {
         let mut c = Container::new();
-        let count = Rc::new(RefCell::new(0));
-        c.add("count", count);
-        Factory { container: Mutex::new(c) }
+        c.declare::<util::UrlParts>("url_parts");
+        c.singleton("counter", |_| Ok(Counter::default()));
+        // Resolved by name from the request's scope, so its needs are declared for verify()
+        c.scoped("visit", |c| {
+            let url_parts = c.try_resolve::<util::UrlParts>("url_parts")?;
+            let counter = c.try_resolve::<Counter>("counter")?;
+            let mut count = counter.0.lock().unwrap();
+            *count += 1;
+            Ok(Visit { number: *count, path: url_parts.path().into() })
+        }).needs::<util::UrlParts>("url_parts").needs::<Counter>("counter");
+        c.add_factory(|| Text);
+        c.add_factory(|| Json);
+        c.bind::<dyn Render, Text>();
+        c.bind_named::<dyn Render, Text>("text");
+        c.bind_named::<dyn Render, Json>("json");
+        // Each contributes to a multibinding, which is resolved as a whole
+        middleware(&mut c);
+        health_checks(&mut c);
+        Factory { container: c }
     }
 }
 
 impl responders::Responder for Factory {
-    fn handle(&self, request: &tiny_http::Request) -> tiny_http::ResponseBox {
-        let url_parts = util::strip_url_prefix(request.url(), "/factory");
+    fn handle(&self, _request: &request::Request, url_parts: &util::UrlParts)
+            -> responders::Response {
+        // Everything bound to or created for this request is dropped with the scope
+        let mut scope = self.container.scope();
+        if url_parts.path() == "/health" {
+            return health(&*scope.try_resolve_type::<Map<&'static str, dyn HealthCheck>>()?);
+        }
+        scope.add("url_parts", url_parts.clone());
+        let visit = scope.try_resolve_type::<Visit>()?;
+        let renderer = match url_parts.query().get("format") {
+            Some(format) => scope.try_resolve::<dyn Render>(format).map_err(|_| {
+                RivetError::BadRequest(format!("Unknown format {:?}", format))
+            })?,
+            None => scope.try_resolve_type::<dyn Render>()?,
+        };
+        let middleware = scope.try_resolve_type::<Set<dyn Middleware>>()?;
+        middleware.iter().fold(renderer.render(&visit), |response, m| m.apply(&visit, response))
+            .build()
+    }
 
-        let mut container = self.container.lock().unwrap();
-        container.add("url_parts", url_parts);
-        let count: Rc<RefCell<i32>> = container.resolve("count");
-        *count.borrow_mut() += 1;
-        util::success(&format!("Count {:?}", count))
+    fn diagnostics(&self) -> Vec<String> {
+        self.container.verify().iter().map(|problem| problem.to_string()).collect()
     }
 }
 
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use responders::Responder;
+    use responders::testing;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::{Barrier, mpsc};
+    use std::thread;
+    use std::time::Duration;
+
+    #[test]
+    fn lifetimes() {
+        let created = Arc::new(AtomicUsize::new(0));
+        let mut app = Container::new();
+        let counter = created.clone();
+        app.singleton("singleton", move |_| Ok(counter.fetch_add(1, Ordering::SeqCst)));
+        app.scoped("scoped", |c| Ok(*c.try_resolve::<usize>("singleton")? + 10));
+        app.transient("transient", |_| Ok(Mutex::new(())));
+        assert_eq!(created.load(Ordering::SeqCst), 0, "singletons are created lazily");
+
+        let first = app.scope();
+        let second = app.scope();
+        assert!(Arc::ptr_eq(&first.resolve::<usize>("singleton"), &second.resolve("singleton")));
+        assert!(Arc::ptr_eq(&first.resolve::<usize>("scoped"), &first.resolve("scoped")));
+        assert!(!Arc::ptr_eq(&first.resolve::<usize>("scoped"), &second.resolve("scoped")));
+        let transient = first.resolve::<Mutex<()>>("transient");
+        assert!(!Arc::ptr_eq(&transient, &first.resolve("transient")));
+        assert_eq!(created.load(Ordering::SeqCst), 1);
+    }
+
+    #[test]
+    fn scopes_shadow_their_parent() {
+        let mut app = Container::new();
+        app.add("name", "app".to_string());
+        let mut scope = app.scope();
+        scope.add("name", "request".to_string());
+        assert_eq!(*scope.resolve::<String>("name"), "request");
+        assert_eq!(*app.resolve::<String>("name"), "app");
+    }
+
+    #[test]
+    #[should_panic(expected = "can only be resolved within a scope()")]
+    fn scoped_outside_scope() {
+        let mut app = Container::new();
+        app.scoped("scoped", |_| Ok(1));
+        app.resolve::<i32>("scoped");
+    }
+
+    #[test]
+    fn resolve_errors() {
+        let mut app = Container::new();
+        app.add("count", 1_i32);
+        app.singleton("never", |_| -> Result<i32, _> { panic!("Shouldn't be constructed") });
+        assert_eq!(app.try_resolve::<i32>("cuont").unwrap_err(), ResolveError::Missing {
+            name: "cuont".into(), requested: "i32", available: vec!["count".into(), "never".into()],
+        });
+        assert_eq!(app.try_resolve::<u64>("never").unwrap_err(), ResolveError::WrongType {
+            name: "never".into(), requested: "u64", stored: "i32",
+        });
+        assert_eq!(app.try_resolve::<i32>("count"), Ok(Arc::new(1)));
+
+        assert_eq!(app.resolve_optional::<i32>("count"), Ok(Some(Arc::new(1))));
+        assert_eq!(app.resolve_optional::<i32>("missing"), Ok(None));
+        assert!(app.resolve_optional::<u8>("count").is_err());
+
+        app.declare::<String>("name");
+        assert_eq!(app.scope().try_resolve::<String>("name").unwrap_err(),
+                   ResolveError::NotAdded { name: "name".into() });
+
+        // Constructors' own resolve errors are returned rather than panicking, and the singleton
+        // can still be constructed once its dependency is bound
+        app.singleton("doubled", |c| Ok(*c.try_resolve::<i32>("counted")? * 2));
+        let error = app.try_resolve::<i32>("doubled").unwrap_err();
+        assert!(matches!(error, ResolveError::Missing { ref name, .. } if name == "counted"));
+        assert_eq!(RivetError::from(error).status_code(), 500);
+        app.add("counted", 21_i32);
+        assert_eq!(app.try_resolve::<i32>("doubled"), Ok(Arc::new(42)));
+    }
+
+    #[test]
+    fn verify() {
+        let mut app = Container::new();
+        app.declare::<String>("name");
+        app.singleton("greeting", |c| Ok(format!("Hi {}", c.try_resolve::<String>("name")?)))
+            .needs::<String>("name");
+        app.transient("typo", |c| Ok(*c.try_resolve::<i32>("cuont")?)).needs::<i32>("cuont");
+        app.transient("wrong", |c| Ok(*c.try_resolve::<i32>("name")?)).needs::<i32>("name");
+        app.transient("a", |c| Ok(*c.try_resolve::<i32>("b")?)).needs::<i32>("b");
+        app.transient("b", |c| Ok(*c.try_resolve::<i32>("a")?)).needs::<i32>("a");
+        app.add_transient_factory(|byte: Arc<u8>| *byte as u16);
+
+        let problems: Vec<_> = app.verify().iter().map(|p| p.to_string()).collect();
+        assert_eq!(problems, vec![
+            "singleton \"greeting\" can't depend on \"name\", which is scoped",
+            "\"typo\" depends on a missing or invalid binding: No binding named \"cuont\" \
+             (requested as i32); available bindings are: a, b, greeting, name, typo, u16, wrong",
+            "\"u16\" depends on a missing or invalid binding: No binding of type u8",
+            "\"wrong\" depends on a missing or invalid binding: \"name\" was requested as i32, \
+             but is bound to alloc::string::String",
+            "dependency cycle: a -> b -> a",
+        ]);
+        assert!(Factory::new().diagnostics().is_empty());
+    }
+
+    #[test]
+    fn constructor_injection() {
+        struct Db(&'static str);
+        struct Service {
+            db: Arc<Db>,
+            table: Arc<String>,
+        }
+
+        let mut app = Container::new();
+        app.add("db", Db("postgres"));
+        app.add("table", "users".to_string());
+        app.add_factory(|db: Arc<Db>, table: Arc<String>| Service { db, table });
+        app.add_transient_factory(|service: Arc<Service>| service.table.len());
+        let service = app.try_resolve_type::<Service>().ok().unwrap();
+        assert_eq!((service.db.0, service.table.as_str()), ("postgres", "users"));
+        assert!(Arc::ptr_eq(&service, &app.try_resolve_type().ok().unwrap()));
+        assert_eq!(app.try_resolve_type::<usize>(), Ok(Arc::new(5)));
+        assert!(app.verify().is_empty());
+
+        // Parameters are resolved from the innermost container with a binding of their type
+        let mut scope = app.scope();
+        scope.add("other", "accounts".to_string());
+        assert_eq!(*scope.try_resolve_type::<String>().unwrap(), "accounts");
+        scope.add("another", "orders".to_string());
+        assert_eq!(scope.try_resolve_type::<String>().unwrap_err(), ResolveError::Ambiguous {
+            requested: "alloc::string::String", names: vec!["another".into(), "other".into()],
+        });
+    }
+
+    #[test]
+    fn cycles() {
+        struct A;
+        struct B;
+        struct C;
+
+        let mut app = Container::new();
+        app.add_factory(|_: Arc<B>| A);
+        app.add_transient_factory(|_: Arc<C>| B);
+        app.add_factory(|_: Arc<A>| C);
+        let (a, b, c) = (any::type_name::<A>(), any::type_name::<B>(), any::type_name::<C>());
+        let cycle = ResolveError::Cycle(vec![a.into(), b.into(), c.into(), a.into()]);
+        assert_eq!(app.try_resolve_type::<A>().err(), Some(cycle.clone()));
+        assert_eq!(app.verify(), vec![cycle]);
+        assert_eq!(app.try_resolve_type::<C>().err(),
+                   Some(ResolveError::Cycle(vec![c.into(), a.into(), b.into(), c.into()])));
+    }
+
+    #[test]
+    fn concurrent_cycles() {
+        // Each constructor waits for the other thread, so both are constructing at once
+        let barrier = Arc::new(Barrier::new(2));
+        let mut app = Container::new();
+        let wait = barrier.clone();
+        app.singleton("a", move |c| { wait.wait(); Ok(*c.try_resolve::<i32>("b")?) });
+        app.singleton("b", move |c| { barrier.wait(); Ok(*c.try_resolve::<i32>("a")?) });
+        let app = Arc::new(app);
+
+        let (sender, receiver) = mpsc::channel();
+        for &name in &["a", "b"] {
+            let (app, sender) = (app.clone(), sender.clone());
+            thread::spawn(move || sender.send((name, app.try_resolve::<i32>(name))).unwrap());
+        }
+        let mut results: Vec<_> = (0..2).map(|_| {
+            receiver.recv_timeout(Duration::from_secs(10)).expect("Deadlocked resolving a cycle")
+        }).collect();
+        results.sort_by_key(|&(name, _)| name);
+        let cycle = |names: &[&str]| {
+            Err(ResolveError::Cycle(names.iter().map(|&n| n.into()).collect()))
+        };
+        assert_eq!(results, vec![("a", cycle(&["a", "b", "a"])), ("b", cycle(&["b", "a", "b"]))]);
+    }
+
+    #[test]
+    fn counts_requests() {
+        let factory = Factory::new();
+        let call = |path| testing::text(testing::get(&factory, path));
+        assert_eq!(call("/a").unwrap(), "Count 1\nPath: /a");
+        assert_eq!(call("/b/c?format=text").unwrap(), "Count 2\nPath: /b/c");
+        assert_eq!(call("/d?format=json").unwrap(), r#"{"number":3,"path":"/d"}"#);
+        assert_eq!(call("/e?format=xml").unwrap_err().status_code(), 400);
+        assert_eq!(call("/health").unwrap(), "counter: ok\nrender: ok");
+    }
+
+    #[test]
+    fn middleware() {
+        let response = testing::get(&Factory::new(), "/").unwrap();
+        let headers: Vec<_> = response.headers().iter()
+            .filter(|h| !h.field.equiv("Content-Type"))
+            .map(|h| h.to_string())
+            .collect();
+        assert_eq!(headers, vec!["Cache-Control: no-store", "X-Visit: 1"]);
+    }
+
+    #[test]
+    fn multibindings() {
+        let mut app = Container::new();
+        app.add("host", "db.local".to_string());
+        app.add_to_set(Arc::new(Memory) as Arc<dyn Storage>);
+        app.add_set_factory(|host: Arc<String>| Arc::new(Postgres(host)) as Arc<dyn Storage>);
+        app.add_to_map("primary", Arc::new(Memory) as Arc<dyn Storage>);
+        app.add_to_map("replica", Arc::new(Memory) as Arc<dyn Storage>);
+        // Injected constructors receive everything contributed
+        app.add_transient_factory(|all: Arc<Set<dyn Storage>>| {
+            all.iter().map(|s| s.name()).collect::<Vec<_>>()
+        });
+        assert!(app.verify().is_empty());
+        assert_eq!(*app.try_resolve_type::<Vec<String>>().unwrap(),
+                   vec!["memory", "postgres://db.local"]);
+
+        // A scope's contributions are added to its parent's, replacing entries with the same key
+        let mut scope = app.scope();
+        scope.add("replica", "db2.local".to_string());
+        scope.add_set_factory(|| Arc::new(Memory) as Arc<dyn Storage>);
+        scope.add_map_factory("replica", |host: Arc<String>| {
+            Arc::new(Postgres(host)) as Arc<dyn Storage>
+        });
+        assert_eq!(scope.try_resolve_type::<Set<dyn Storage>>().ok().unwrap().len(), 3);
+        let map = scope.try_resolve_type::<Map<&str, dyn Storage>>().ok().unwrap();
+        let names: Vec<_> = map.iter().map(|(k, s)| format!("{} {}", k, s.name())).collect();
+        assert_eq!(names, vec!["primary memory", "replica postgres://db2.local"]);
+        assert_eq!(app.try_resolve_type::<Map<&str, dyn Storage>>().ok().unwrap().len(), 2);
+
+        let mut broken = Container::new();
+        broken.add_set_factory(|host: Arc<String>| Arc::new(Postgres(host)) as Arc<dyn Storage>);
+        let problems: Vec<_> = broken.verify().iter().map(|p| p.to_string()).collect();
+        assert_eq!(problems, vec![format!(
+            "\"{}\" depends on a missing or invalid binding: No binding of type \
+             alloc::string::String", any::type_name::<Set<dyn Storage>>())]);
+    }
+
+    #[test]
+    #[should_panic(expected = "\"primary\" is already contributed")]
+    fn conflicting_contributions() {
+        let mut app = Container::new();
+        app.add_to_map("primary", Arc::new(Memory) as Arc<dyn Storage>);
+        app.add_to_map("primary", Arc::new(Memory) as Arc<dyn Storage>);
+    }
+
+    trait Storage: Send + Sync {
+        fn name(&self) -> String;
+    }
+    interface!(Storage);
+
+    struct Postgres(Arc<String>);
+    impl Storage for Postgres {
+        fn name(&self) -> String { format!("postgres://{}", self.0) }
+    }
+
+    struct Memory;
+    impl Storage for Memory {
+        fn name(&self) -> String { "memory".into() }
+    }
+
+    #[test]
+    fn trait_objects() {
+        let mut app = Container::new();
+        app.add("host", "db.local".to_string());
+        app.add_factory(|host: Arc<String>| Postgres(host));
+        app.add_factory(|| Memory);
+        app.bind::<dyn Storage, Postgres>();
+        app.bind_named::<dyn Storage, Memory>("cache");
+        app.add_transient_factory(|storage: Arc<dyn Storage>| storage.name().len());
+        assert!(app.verify().is_empty());
+
+        let storage = app.try_resolve_type::<dyn Storage>().ok().unwrap();
+        assert_eq!(storage.name(), "postgres://db.local");
+        assert_eq!(app.try_resolve::<dyn Storage>("cache").ok().unwrap().name(), "memory");
+        assert_eq!(app.try_resolve_type::<usize>(), Ok(Arc::new(19)));
+
+        // The trait object is the implementation's singleton
+        let postgres: Arc<dyn Storage> = app.try_resolve_type::<Postgres>().ok().unwrap();
+        assert!(Arc::ptr_eq(&storage, &postgres));
+    }
+
+    #[test]
+    fn captive_trait_objects() {
+        let mut app = Container::new();
+        app.add_scoped_factory(|| Memory);
+        app.bind::<dyn Storage, Memory>();
+        app.add_factory(|storage: Arc<dyn Storage>| storage.name());
+        // The singleton would be created from the first request's Memory, via the trait binding
+        assert_eq!(app.verify(), vec![ResolveError::Captive {
+            singleton: "alloc::string::String".into(),
+            scoped: any::type_name::<dyn Storage>().into(),
+        }]);
+    }
+}
@@ -12,21 +12,131 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
+pub mod admin;
 pub mod closure;
+pub mod codegen;
 pub mod factory;
 pub mod pattern;
 pub mod raw;
+pub mod routes;
+pub mod static_files;
 pub mod stringly;
+#[cfg(test)]
+pub mod testing;
 pub mod traits;
 pub mod traits_macro;
 
+use error::RivetError;
+use request;
 use tiny_http;
+use util;
+
+/// The result of handling a request.
+pub type Response = Result<tiny_http::ResponseBox, RivetError>;
 
 /// Our plugins implement this trait, accepting HTTP requests and returning HTTP responses.
 ///
 /// They should in turn expose a more user-friendly API for how those requests should be handled.
 /// For example, a plugin might support parsing data out of the URL path and provide those values
 /// to the callback.
-pub trait Responder {
-    fn handle(&self, &tiny_http::Request) -> tiny_http::ResponseBox;
+///
+/// Each responder is mounted at a URL prefix, and is passed the parsed remainder of the URL after
+/// that prefix; e.g. a responder mounted at `/api` would receive `/foo?bar` for `/api/foo?bar`.
+/// This means the same responder doesn't need to know, or hard-code, where it's mounted.
+///
+/// Requests are dispatched from a pool of worker threads, so responders must be safe to share
+/// between threads; any mutable state needs to be guarded (e.g. with a `Mutex`).
+///
+/// Failures are returned as a `RivetError`, which the server maps to an HTTP status and body in
+/// one place, rather than each responder building its own error responses.
+pub trait Responder: Send + Sync {
+    fn handle(&self, request: &request::Request, url: &util::UrlParts) -> Response;
+
+    /// Problems with how the responder is set up, such as ambiguous or unreachable routes or
+    /// missing dependencies, which are reported when the server starts. With `--strict` the server
+    /// won't start.
+    fn diagnostics(&self) -> Vec<String> { Vec::new() }
+}
+
+/// A responder that can be enabled or disabled in the server's configuration; enabled responders
+/// are mounted at `/<name>`.
+pub struct Registration {
+    pub name: &'static str,
+    pub title: &'static str,
+    pub description: &'static str,
+    /// An example URL (relative to the mount point) to link to from the homepage
+    pub example: &'static str,
+    pub create: fn() -> Box<dyn Responder>,
+}
+
+/// All the responders the server knows about. Add new responders here.
+pub const REGISTRY: &[Registration] = &[
+    Registration {
+        name: "raw", title: "Raw",
+        description: "handle Request object directly",
+        example: "/foo/bar?baz", create: || Box::new(raw::Raw {}),
+    },
+    Registration {
+        name: "stringly", title: "Stringly",
+        description: "pass in fixed request details",
+        example: "/foo/bar?baz", create: || Box::new(stringly::Stringly {}),
+    },
+    Registration {
+        name: "pattern", title: "Pattern",
+        description: "route requests by regex patterns",
+        example: "/foo/bar?baz", create: || Box::new(pattern::example()),
+    },
+    Registration {
+        name: "closure", title: "Closure",
+        description: "route requests to user-specified closures",
+        example: "/both/bar?baz", create: || Box::new(closure::Closure {}),
+    },
+    Registration {
+        name: "factory", title: "Factory",
+        description: "DI pattern using generic factory",
+        example: "/", create: || Box::new(factory::Factory::new()),
+    },
+    Registration {
+        name: "traits", title: "Traits",
+        description: "DI pattern providing some type safety via traits",
+        example: "/bar?baz", create: || Box::new(traits::Traits {}),
+    },
+    Registration {
+        name: "traits_macro", title: "Traits Macro",
+        description: "same as above, but simplified by macros",
+        example: "/bar?baz", create: || Box::new(traits_macro::TraitsMacro {}),
+    },
+    Registration {
+        name: "codegen", title: "Codegen",
+        description: "handlers declared with #[route] attributes",
+        example: "/users/7?verbose", create: codegen::example,
+    },
+];
+
+/// Looks up a responder in the `REGISTRY` by name.
+pub fn registration(name: &str) -> Option<&'static Registration> {
+    REGISTRY.iter().find(|r| r.name == name)
+}
+
+/// Responds to a request whose path matched, but only for other (`allowed`) methods: with the
+/// methods that are allowed - as a 405 error, or the answer to an OPTIONS request.
+pub fn method_not_allowed(method: &tiny_http::Method, mut allowed: Vec<tiny_http::Method>)
+        -> Response {
+    use tiny_http::Method;
+    if allowed.contains(&Method::Get) {
+        allowed.push(Method::Head);
+    }
+    allowed.push(Method::Options);
+    let mut unique = Vec::new();
+    for method in allowed {
+        if !unique.contains(&method) {
+            unique.push(method);
+        }
+    }
+
+    if *method == Method::Options {
+        util::ResponseBuilder::ok().allow(&unique).build()
+    } else {
+        Err(RivetError::MethodNotAllowed { allowed: unique })
+    }
 }
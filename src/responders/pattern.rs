@@ -13,58 +13,590 @@
 // limitations under the License.
 
 use regex;
+use regex_syntax::{CharClass, Expr, Repeater};
+use error::RivetError;
+use request;
 use responders;
-use std::collections::HashMap;
-use tiny_http;
+use std::fmt;
+use std::str::FromStr;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use tiny_http::Method;
 use util;
+use uuid::Uuid;
 
-lazy_static! {
-    // Add routes to this vector
-    // Note that the order matters - the first matched pattern will be used
-    static ref ROUTES: Vec<Route> = vec![
-        Route::new("/foo/([^/]*)", handle_foo),
-        Route::new("", handle)
-    ];
+/// The routes served by the `pattern` responder in the `REGISTRY`.
+pub fn example() -> Pattern {
+    let hits = Arc::new(AtomicUsize::new(0));
+    Pattern::builder()
+        .route(Route::new(&[Method::Get], "/foo/(?P<name>[^/]*)", handle_foo))
+        .route(Route::new(&[Method::Delete], "/foo/(?P<name>[^/]*)", delete_foo))
+        .route(Route::new(&[Method::Get], r"/users/(?P<id>\d+)", show_user))
+        .route(Route::new(&[Method::Get], "/orders/(?P<id>[^/]+)", show_order))
+        .route(Route::new(&[Method::Get], "/hits", move |(): (), _: &util::Query| {
+            format!("Hits: {}", hits.fetch_add(1, Ordering::Relaxed) + 1)
+        }))
+        .route(Route::new(&[Method::Get, Method::Post], "/", handle))
+        .build()
 }
 
-struct Route {
+type Callback = Box<dyn Fn(&Params, &util::Query) -> Result<String, RivetError> + Send + Sync>;
+
+/// A path pattern and the methods it accepts, routed to a callback.
+pub struct Route {
+    /// The methods this route responds to; GET routes also respond to HEAD
+    methods: Vec<Method>,
     path: regex::Regex,
-    callback: fn(&regex::Captures, &HashMap<String, String>) -> String
+    callback: Callback
 }
 
 impl Route {
-    pub fn new(path: &str, callback: fn(&regex::Captures, &HashMap<String, String>) -> String) -> Route {
-        Route { path: regex::Regex::new(&format!("^{}$", path)).unwrap(), callback }
+    /// Creates a route matching the whole of `path`, a regex. The callback is passed the route's
+    /// named captures, either parsed into a tuple with one value per capture in order (e.g.
+    /// `|(id,): (u64,), query: &util::Query|`) or as the raw `Params`. Requests where a capture
+    /// doesn't parse are rejected with a 400, without calling the callback. Panics if the tuple
+    /// doesn't have one value per named capture.
+    ///
+    /// The callback may be a plain `fn` or a closure capturing state, which must be safe to share
+    /// between worker threads.
+    pub fn new<Args, F>(methods: &[Method], path: &str, callback: F) -> Route
+            where Args: FromParams + 'static,
+                  F: Fn(Args, &util::Query) -> String + Send + Sync + 'static {
+        let path = regex::Regex::new(&format!("^{}$", path)).unwrap();
+        let captures = path.capture_names().flatten().count();
+        match Args::arity() {
+            Some(arity) if arity != captures => panic!(
+                "Route {} has {} named capture(s), but its callback takes {}",
+                path, captures, arity),
+            _ => {},
+        }
+        let callback = move |params: &Params, query: &util::Query| {
+            Args::from_params(params).map(|args| callback(args, query))
+        };
+        Route { methods: methods.to_vec(), path, callback: Box::new(callback) }
+    }
+
+    fn expr(&self) -> Expr {
+        Expr::parse(self.path.as_str()).expect("Route patterns are valid regexes")
+    }
+
+    /// Sample paths this route matches, and whether they're all the paths it matches.
+    fn examples(&self) -> (Vec<String>, bool) {
+        let expr = self.expr();
+        let examples = examples(&expr);
+        let complete = exhaustive(&expr) && examples.len() < MAX_EXAMPLES;
+        (examples, complete)
+    }
+
+    /// Whether every path this route matches is also matched by `earlier`'s pattern, as far as
+    /// can be told without comparing the regexes: either the patterns are the same, or `earlier`
+    /// is a literal prefix followed by `.*` and this route's paths all start with that prefix.
+    fn shadowed_by(&self, earlier: &Route) -> bool {
+        if self.path.as_str() == earlier.path.as_str() {
+            return true;
+        }
+        match wildcard_prefix(&earlier.expr()) {
+            Some(prefix) => literal_prefix(&self.expr()).starts_with(&prefix),
+            None => false,
+        }
+    }
+
+    fn allows(&self, method: &Method) -> bool {
+        self.methods.contains(method) ||
+            (*method == Method::Head && self.methods.contains(&Method::Get))
+    }
+
+    /// Extracts the named captures. Patterns are matched against the percent-encoded path, but
+    /// the captures are decoded, like `path_components()`.
+    fn params(&self, captures: &regex::Captures) -> Params {
+        let captures = self.path.capture_names().flatten().map(|name| {
+            (name.to_string(), captures.name(name).map(|v| util::percent_decode(v.as_str())))
+        }).collect();
+        Params { captures }
+    }
+}
+
+impl fmt::Display for Route {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let methods: Vec<_> = self.methods.iter().map(|m| m.to_string()).collect();
+        let path = self.path.as_str();
+        write!(f, "{} {}", methods.join(","), &path[1..path.len() - 1])
+    }
+}
+
+/// The named captures of a matched route, in the order they appear in its pattern.
+#[derive(Clone)]
+pub struct Params {
+    /// Each capture's name and its decoded text, if it participated in the match
+    captures: Vec<(String, Option<String>)>,
+}
+
+impl Params {
+    /// The (decoded, but unparsed) text of a named capture, if it participated in the match.
+    #[cfg_attr(not(test), allow(dead_code))]
+    pub fn get_str(&self, name: &str) -> Option<&str> {
+        self.captures.iter().find(|&(n, _)| n == name).and_then(|(_, v)| v.as_deref())
+    }
+}
+
+impl fmt::Debug for Params {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let matched = self.captures.iter().filter_map(|(k, v)| v.as_ref().map(|v| (k, v)));
+        f.debug_map().entries(matched).finish()
+    }
+}
+
+/// The arguments a `Route`'s callback takes from its named captures: `Params` for all of them as
+/// text, or a tuple of up to 12 `FromStr` values, one for each capture in order.
+pub trait FromParams: Sized {
+    /// How many captures this takes, or `None` if it takes any number of them.
+    fn arity() -> Option<usize>;
+
+    /// Parses the captures, failing with a `BadRequest` if any of them don't parse.
+    fn from_params(params: &Params) -> Result<Self, RivetError>;
+}
+
+impl FromParams for Params {
+    fn arity() -> Option<usize> { None }
+
+    fn from_params(params: &Params) -> Result<Params, RivetError> { Ok(params.clone()) }
+}
+
+macro_rules! from_params {
+    ($($arg:ident),*) => {
+        impl<$($arg: FromStr),*> FromParams for ($($arg,)*) where $($arg::Err: fmt::Display),* {
+            fn arity() -> Option<usize> { Some(<[&str]>::len(&[$(stringify!($arg)),*])) }
+
+            #[allow(unused_variables, unused_mut)]
+            fn from_params(params: &Params) -> Result<Self, RivetError> {
+                let mut captures = params.captures.iter();
+                Ok(($(parse_capture::<$arg>(captures.next().expect("Checked by Route::new"))?,)*))
+            }
+        }
     }
 }
 
-fn handle(url_captures: &regex::Captures, url_params: &HashMap<String, String>) -> String {
+for_each_arity!(from_params);
+
+fn parse_capture<T: FromStr>(capture: &(String, Option<String>)) -> Result<T, RivetError>
+        where T::Err: fmt::Display {
+    let (ref name, ref value) = *capture;
+    let value = value.as_ref()
+        .ok_or_else(|| RivetError::BadRequest(format!("Missing {}", name)))?;
+    value.parse()
+        .map_err(|e| RivetError::BadRequest(format!("Invalid {} {:?}: {}", name, value, e)))
+}
+
+fn handle(url_captures: Params, url_params: &util::Query) -> String {
     format!("pattern!\nURL captures: {:?}\nQuery args: {:?}", url_captures, url_params)
 }
 
-fn handle_foo(_url_captures: &regex::Captures, url_params: &HashMap<String, String>) -> String {
-    format!("Foo!\nQuery args: {:?}", url_params)
+fn handle_foo((name,): (String,), url_params: &util::Query) -> String {
+    format!("Foo {}!\nQuery args: {:?}", name, url_params)
+}
+
+fn delete_foo((name,): (String,), _url_params: &util::Query) -> String {
+    format!("Deleted {}!", name)
+}
+
+fn show_user((id,): (u64,), _url_params: &util::Query) -> String {
+    format!("User #{} (next is #{})", id, id + 1)
+}
+
+fn show_order((id,): (Uuid,), _url_params: &util::Query) -> String {
+    format!("Order {}", id.hyphenated())
 }
 
 /// Regex-based responder, routes requests to separate URLs to different functions
+///
+/// Each instance has its own routes, registered with `Pattern::builder()`, so the same responder
+/// type can be mounted several times serving different routes. The routes are compiled into a
+/// single `RegexSet`, so a request's path is matched against all of them in one pass.
 pub struct Pattern {
+    routes: Vec<Route>,
+    paths: regex::RegexSet,
 }
 
-impl responders::Responder for Pattern {
-    fn handle(&self, request: &tiny_http::Request) -> tiny_http::ResponseBox {
-        let url_parts = util::strip_url_prefix(request.url(), "/pattern");
-
-        for route in ROUTES.iter() {
-            match route.path.captures(url_parts.path()) {
-                Some(captures) => {
-                    let callback = &route.callback;
-                    let response = callback(&captures, url_parts.query());
-                    return util::success(&response);
+impl Pattern {
+    pub fn builder() -> PatternBuilder {
+        PatternBuilder { routes: Vec::new() }
+    }
+
+    /// Calls the first route matching the method and path. If only the path matches, responds
+    /// with the methods that are allowed - as a 405 error, or the answer to an OPTIONS request.
+    fn dispatch(&self, method: &Method, url_parts: &util::UrlParts) -> responders::Response {
+        let mut allowed = Vec::new();
+        for route in self.paths.matches(url_parts.path()).iter().map(|i| &self.routes[i]) {
+            if route.allows(method) {
+                let captures = route.path.captures(url_parts.path())
+                    .expect("RegexSet and Regex should agree");
+                let response = (route.callback)(&route.params(&captures), url_parts.query())?;
+                return util::ResponseBuilder::ok().text(&response).build();
+            }
+            allowed.extend(route.methods.iter().cloned());
+        }
+
+        if allowed.is_empty() {
+            return Err(RivetError::NotFound("No matched pattern".into()));
+        }
+        responders::method_not_allowed(method, allowed)
+    }
+
+    /// Looks for routes that are (partly) shadowed by earlier routes, since the first matching
+    /// route wins. Regexes can't be compared exactly, so this checks a sample of paths each route
+    /// matches against the routes before it; an empty result isn't a guarantee. Routes are only
+    /// reported as `Unreachable` when that's certain - otherwise a route whose samples are all
+    /// shadowed is reported as overlapping.
+    pub fn check_routes(&self) -> Vec<RouteIssue> {
+        let mut issues = Vec::new();
+        for (later, route) in self.routes.iter().enumerate() {
+            let shadowed = route.methods.iter().all(|m| self.routes[..later].iter()
+                .any(|earlier| earlier.methods.contains(m) && route.shadowed_by(earlier)));
+            if shadowed {
+                issues.push(RouteIssue::Unreachable { route: route.to_string() });
+                continue;
+            }
+            let mut overlaps: Vec<RouteIssue> = Vec::new();
+            let mut reachable = false;
+            let (examples, complete) = route.examples();
+            // Requests always have a normalized path, so other strings don't count
+            for example in examples.into_iter().filter(|e| e.starts_with('/')) {
+                let mut shadowed = Vec::new();
+                for earlier in self.routes[..later].iter() {
+                    let shared: Vec<_> =
+                        route.methods.iter().filter(|m| earlier.methods.contains(m)).collect();
+                    if shared.is_empty() || !earlier.path.is_match(&example) {
+                        continue;
+                    }
+                    shadowed.extend(shared);
+                    if !overlaps.iter().any(|o| o.earlier() == Some(&earlier.to_string())) {
+                        overlaps.push(RouteIssue::Overlap {
+                            earlier: earlier.to_string(), later: route.to_string(), example });
+                        break;
+                    }
                 }
-                None => {}
+                if route.methods.iter().any(|m| !shadowed.contains(&m)) {
+                    reachable = true;
+                }
+            }
+            if reachable || !complete {
+                issues.extend(overlaps);
+            } else {
+                issues.push(RouteIssue::Unreachable { route: route.to_string() });
+            }
+        }
+        issues
+    }
+}
+
+impl responders::Responder for Pattern {
+    fn handle(&self, request: &request::Request, url_parts: &util::UrlParts)
+            -> responders::Response {
+        self.dispatch(request.method(), url_parts)
+    }
+
+    fn diagnostics(&self) -> Vec<String> {
+        self.check_routes().iter().map(|issue| issue.to_string()).collect()
+    }
+}
+
+/// A potential mistake in a `Pattern`'s routes, see `Pattern::check_routes()`.
+#[derive(Debug, Clone, PartialEq)]
+pub enum RouteIssue {
+    /// Some requests the later route matches, such as `example`, are handled by the earlier route.
+    /// Possibly all of them, if `Pattern::check_routes()` couldn't tell.
+    Overlap { earlier: String, later: String, example: String },
+    /// The route is never used, either because earlier routes handle every request it matches or
+    /// because it doesn't match any request path
+    Unreachable { route: String },
+}
+
+impl RouteIssue {
+    fn earlier(&self) -> Option<&String> {
+        match *self {
+            RouteIssue::Overlap { ref earlier, .. } => Some(earlier),
+            RouteIssue::Unreachable { .. } => None,
+        }
+    }
+}
+
+impl fmt::Display for RouteIssue {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            RouteIssue::Overlap { ref earlier, ref later, ref example } =>
+                write!(f, "route {} overlaps earlier route {}, which will handle e.g. {}",
+                       later, earlier, example),
+            RouteIssue::Unreachable { ref route } =>
+                write!(f, "route {} is unreachable", route),
+        }
+    }
+}
+
+pub struct PatternBuilder {
+    routes: Vec<Route>,
+}
+
+impl PatternBuilder {
+    /// Adds a route. Note that the order matters - the first route matching the path and method
+    /// will be used.
+    pub fn route(mut self, route: Route) -> PatternBuilder {
+        self.routes.push(route);
+        self
+    }
+
+    pub fn build(self) -> Pattern {
+        let paths = regex::RegexSet::new(self.routes.iter().map(|r| r.path.as_str()))
+            .expect("Route patterns are valid regexes");
+        Pattern { routes: self.routes, paths }
+    }
+}
+
+/// The most examples generated for any one (sub)expression, to keep the combinations in check.
+const MAX_EXAMPLES: usize = 16;
+
+/// Generates a sample of strings matching `expr`, favoring the shortest ones (e.g. `*` is
+/// repeated zero, one, and two times).
+fn examples(expr: &Expr) -> Vec<String> {
+    let mut examples = match *expr {
+        Expr::Empty => vec![],
+        Expr::Literal { ref chars, .. } => vec![chars.iter().collect()],
+        Expr::AnyChar | Expr::AnyCharNoNL => vec!["a".into(), "/".into()],
+        Expr::Class(ref class) => class_examples(class).iter().map(char::to_string).collect(),
+        Expr::Group { ref e, .. } => examples(e),
+        Expr::Repeat { ref e, r, .. } => {
+            let inner = examples(e);
+            let counts = match r {
+                Repeater::ZeroOrOne => vec![0, 1],
+                Repeater::ZeroOrMore => vec![0, 1, 2],
+                Repeater::OneOrMore => vec![1, 2],
+                Repeater::Range { min, max } =>
+                    if max.is_some_and(|max| max == min) { vec![min] } else { vec![min, min + 1] },
+            };
+            counts.into_iter()
+                .flat_map(|n| (0..n).fold(vec![String::new()], |acc, _| product(&acc, &inner)))
+                .collect()
+        },
+        Expr::Concat(ref exprs) =>
+            exprs.iter().fold(vec![String::new()], |acc, e| product(&acc, &examples(e))),
+        Expr::Alternate(ref exprs) => exprs.iter().flat_map(examples).collect(),
+        // Anchors and word boundaries don't consume any text; byte-oriented expressions only
+        // appear with (?-u), and are approximated as matching the empty string
+        _ => vec![String::new()],
+    };
+    examples.dedup();
+    examples.truncate(MAX_EXAMPLES);
+    examples
+}
+
+fn product(prefixes: &[String], suffixes: &[String]) -> Vec<String> {
+    prefixes.iter()
+        .flat_map(|p| suffixes.iter().map(move |s| format!("{}{}", p, s)))
+        .take(MAX_EXAMPLES)
+        .collect()
+}
+
+/// Whether `examples()` lists every string `expr` matches (ignoring truncation), rather than a
+/// sample of them.
+fn exhaustive(expr: &Expr) -> bool {
+    match *expr {
+        Expr::Empty | Expr::StartText | Expr::EndText => true,
+        Expr::Literal { casei, .. } => !casei,
+        Expr::Class(ref class) =>
+            class.iter().count() <= 3 && class.iter().all(|range| range.start == range.end),
+        Expr::Group { ref e, .. } => exhaustive(e),
+        Expr::Repeat { ref e, r, .. } => exhaustive(e) && match r {
+            Repeater::ZeroOrOne => true,
+            Repeater::Range { min, max } => max.is_some_and(|max| max <= min + 1),
+            Repeater::ZeroOrMore | Repeater::OneOrMore => false,
+        },
+        Expr::Concat(ref exprs) | Expr::Alternate(ref exprs) => exprs.iter().all(exhaustive),
+        _ => false,
+    }
+}
+
+/// The literal text every string `expr` matches starts with.
+fn literal_prefix(expr: &Expr) -> String {
+    let mut prefix = String::new();
+    if let Expr::Concat(ref exprs) = *expr {
+        for e in exprs {
+            match *e {
+                Expr::StartText => {},
+                Expr::Literal { ref chars, casei: false } => prefix.extend(chars),
+                _ => break,
             }
         }
+    }
+    prefix
+}
+
+/// If `expr` matches every string starting with some literal text (e.g. `^/static/.*$`), that
+/// text.
+fn wildcard_prefix(expr: &Expr) -> Option<String> {
+    let exprs = match *expr {
+        Expr::Concat(ref exprs) => exprs,
+        _ => return None,
+    };
+    let rest: Vec<_> = exprs.iter()
+        .skip_while(|e| matches!(**e, Expr::StartText | Expr::Literal { casei: false, .. }))
+        .collect();
+    match rest[..] {
+        [&Expr::Repeat { ref e, r: Repeater::ZeroOrMore, .. }] |
+        [&Expr::Repeat { ref e, r: Repeater::ZeroOrMore, .. }, &Expr::EndText] => match **e {
+            // Request paths never contain newlines, so `.` matches any of their characters
+            Expr::AnyChar | Expr::AnyCharNoNL => Some(literal_prefix(expr)),
+            _ => None,
+        },
+        _ => None,
+    }
+}
+
+/// A few characters from the class, preferring ones commonly found in paths.
+fn class_examples(class: &CharClass) -> Vec<char> {
+    class.iter().take(3).map(|range| {
+        "a0A-_./".chars().find(|&c| range.start <= c && c <= range.end).unwrap_or(range.start)
+    }).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use responders::Responder;
+    use responders::testing;
+
+    fn call(method: Method, url: &str) -> Result<(u16, String), RivetError> {
+        call_on(&example(), method, url)
+    }
+
+    fn call_on(pattern: &Pattern, method: Method, url: &str) -> Result<(u16, String), RivetError> {
+        pattern.dispatch(&method, &util::UrlParts::new(url))
+            .map(|response| (response.status_code().0, testing::body(response)))
+    }
+
+    #[test]
+    fn routes_by_method() {
+        assert_eq!(call(Method::Get, "/foo/bar").unwrap().1, "Foo bar!\nQuery args: {}");
+        assert_eq!(call(Method::Get, "/foo/a%20b%2Fc").unwrap().1, "Foo a b/c!\nQuery args: {}");
+        assert_eq!(call(Method::Delete, "/foo/bar").unwrap().1, "Deleted bar!");
+        assert_eq!(call(Method::Head, "/foo/bar").unwrap().1, "Foo bar!\nQuery args: {}");
+        assert_eq!(call(Method::Get, "/nope").unwrap_err().status_code(), 404);
+    }
+
+    #[test]
+    fn typed_params() {
+        assert_eq!(call(Method::Get, "/users/41").unwrap().1, "User #41 (next is #42)");
+        assert_eq!(call(Method::Get, "/orders/67E55044-10B1-426F-9247-BB680E5FE0C8").unwrap().1,
+                   "Order 67e55044-10b1-426f-9247-bb680e5fe0c8");
+
+        // Captures that don't parse are rejected before the callback is called
+        let overflow = call(Method::Get, "/users/99999999999999999999").unwrap_err();
+        assert_eq!(overflow.status_code(), 400);
+        assert!(overflow.to_string().starts_with("Invalid id \"99999999999999999999\""));
+        assert_eq!(call(Method::Get, "/orders/42").unwrap_err().status_code(), 400);
+        // Typed captures are parsed after being decoded
+        assert_eq!(call(Method::Get, "/orders/67E55044-10B1-426F-9247-BB680E5FE0C%38").unwrap().1,
+                   "Order 67e55044-10b1-426f-9247-bb680e5fe0c8");
+    }
+
+    #[test]
+    fn optional_captures() {
+        let pattern = Pattern::builder()
+            .route(Route::new(&[Method::Get], "/a(/(?P<x>[a-z]+))?", |p: Params, _: &util::Query| {
+                p.get_str("x").unwrap_or("none").to_string()
+            }))
+            .route(Route::new(&[Method::Get], "/b(/(?P<x>[a-z]+))?",
+                              |(x,): (String,), _: &util::Query| x))
+            .build();
+        assert_eq!(call_on(&pattern, Method::Get, "/a/y").unwrap().1, "y");
+        assert_eq!(call_on(&pattern, Method::Get, "/a").unwrap().1, "none");
+        assert_eq!(call_on(&pattern, Method::Get, "/b/y").unwrap().1, "y");
+        assert_eq!(call_on(&pattern, Method::Get, "/b").unwrap_err().status_code(), 400);
+    }
+
+    #[test]
+    fn separate_instances() {
+        let greeting = String::from("Hello");
+        let hello = Pattern::builder()
+            .route(Route::new(&[Method::Get], "/(?P<name>[a-z]+)",
+                              move |(name,): (String,), _: &util::Query| {
+                                  format!("{} {}", greeting, name)
+                              }))
+            .build();
+        let empty = Pattern::builder().build();
+
+        assert_eq!(call_on(&hello, Method::Get, "/world").unwrap().1, "Hello world");
+        assert_eq!(call_on(&empty, Method::Get, "/world").unwrap_err().status_code(), 404);
+        assert_eq!(call_on(&example(), Method::Get, "/world").unwrap_err().status_code(), 404);
+    }
+
+    #[test]
+    fn stateful_closure() {
+        let pattern = example();
+        assert_eq!(call_on(&pattern, Method::Get, "/hits").unwrap().1, "Hits: 1");
+        assert_eq!(call_on(&pattern, Method::Get, "/hits").unwrap().1, "Hits: 2");
+        assert_eq!(call(Method::Get, "/hits").unwrap().1, "Hits: 1");
+    }
+
+    fn issues(routes: &[(Method, &str)]) -> Vec<String> {
+        let pattern = routes.iter().fold(Pattern::builder(), |builder, (method, path)| {
+            builder.route(Route::new(::std::slice::from_ref(method), path, handle))
+        }).build();
+        pattern.check_routes().iter().map(|issue| issue.to_string()).collect()
+    }
+
+    #[test]
+    fn check_routes() {
+        assert_eq!(example().check_routes(), vec![]);
+        assert_eq!(issues(&[(Method::Get, "/.*"), (Method::Get, r"/users/\d+")]),
+                   vec!["route GET /users/\\d+ is unreachable"]);
+        assert_eq!(issues(&[(Method::Get, "/foo/[^/]*"), (Method::Get, "/(foo|bar)/x")]),
+                   vec!["route GET /(foo|bar)/x overlaps earlier route GET /foo/[^/]*, which will \
+                         handle e.g. /foo/x"]);
+        // Specific routes before general ones are fine, as are routes with different methods
+        let none: Vec<String> = Vec::new();
+        assert_eq!(issues(&[(Method::Get, "/foo/x"), (Method::Get, "/foo/[^/]*")]), none);
+        assert_eq!(issues(&[(Method::Get, "/.*"), (Method::Post, "/x")]), none);
+        // Request paths always start with a /
+        assert_eq!(issues(&[(Method::Get, "")]), vec!["route GET  is unreachable"]);
+        assert_eq!(issues(&[(Method::Get, "/a"), (Method::Get, "/(a|b)?"), (Method::Get, "/a")]),
+                   vec!["route GET /(a|b)? overlaps earlier route GET /a, which will handle e.g. \
+                         /a", "route GET /a is unreachable"]);
+    }
+
+    #[test]
+    fn unproven_shadowing() {
+        // Every sampled path of the later route starts with an a, but /b still reaches it
+        let pattern = Pattern::builder()
+            .route(Route::new(&[Method::Get], "/a.*", handle))
+            .route(Route::new(&[Method::Get], "/[a-z]+", handle))
+            .build();
+        // Not provably unreachable, but still ambiguous
+        assert_eq!(pattern.diagnostics(), vec!["route GET /[a-z]+ overlaps earlier route GET /a.*, \
+                                                which will handle e.g. /a"]);
+        assert_eq!(call_on(&pattern, Method::Get, "/b").unwrap().0, 200);
+        assert_eq!(issues(&[(Method::Get, "/a.*"), (Method::Get, "/ab[0-9]+")]),
+                   vec!["route GET /ab[0-9]+ is unreachable"]);
+    }
+
+    #[test]
+    #[should_panic(expected = "has 1 named capture(s), but its callback takes 2")]
+    fn callback_arity() {
+        Route::new(&[Method::Get], r"/users/(?P<id>\d+)", |_: (u64, u64), _: &util::Query| {
+            String::new()
+        });
+    }
+
+    #[test]
+    fn method_not_allowed() {
+        assert_eq!(call(Method::Post, "/foo/bar").unwrap_err(), RivetError::MethodNotAllowed {
+            allowed: vec![Method::Get, Method::Delete, Method::Head, Method::Options] });
+    }
 
-        util::fail404("No matched pattern")
+    #[test]
+    fn options() {
+        let url = util::UrlParts::new("/foo/bar");
+        let response = example().dispatch(&Method::Options, &url).unwrap();
+        assert_eq!(response.status_code().0, 200);
+        let allow = response.headers().iter().find(|h| h.field.equiv("Allow")).unwrap();
+        assert_eq!(allow.value.as_str(), "GET, DELETE, HEAD, OPTIONS");
     }
 }
@@ -12,15 +12,35 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
+use request;
 use responders;
-use tiny_http;
 use util;
 
 /// Basic Responder implementation just demonstrating the API.
 pub struct Raw {}
 
 impl responders::Responder for Raw {
-    fn handle(&self, request: &tiny_http::Request) -> tiny_http::ResponseBox {
-        util::success(&format!("Raw! {}", util::strip_prefix(request.url(), "/raw")))
+    fn handle(&self, request: &request::Request, url_parts: &util::UrlParts)
+            -> responders::Response {
+        // The path relative to where this responder is mounted, with the query as it was sent
+        let query = request.url().find('?').map_or("", |i| &request.url()[i..]);
+        util::ResponseBuilder::ok().text(&format!("Raw! {}{}", url_parts.path(), query)).build()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use responders::Responder;
+    use responders::testing;
+    use tiny_http;
+
+    #[test]
+    fn relative_to_mount() {
+        let raw: tiny_http::Request =
+            tiny_http::TestRequest::new().with_path("/raw/foo?b%20r").into();
+        let request = request::Request::new(&raw, request::Body::default());
+        let response = Raw {}.handle(&request, &util::UrlParts::new("/foo?b%20r"));
+        assert_eq!(testing::text(response).unwrap(), "Raw! /foo?b%20r");
     }
 }
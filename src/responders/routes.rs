@@ -0,0 +1,212 @@
+// Copyright 2017 Google LLC, Matthew Vilim
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//      http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Runtime support for handlers declared with the `rivet_codegen` macros. `#[route]` generates a
+//! `Route` for each handler, and `routes![...]` collects them to be served by a `Routes`
+//! responder, which builds the binder the handlers' dependencies are injected from.
+
+use error::RivetError;
+use request;
+use responders;
+use std::fmt;
+use std::str::FromStr;
+use tiny_http::{Method, ResponseBox};
+use util;
+
+/// Builds the binder for a request, once it's been matched to a route.
+pub type DepsFactory<D> = fn(&request::Request, &util::UrlParts) -> Result<D, RivetError>;
+
+/// A segment of a route's path template, as parsed by `#[route]`.
+#[derive(Debug, PartialEq)]
+pub enum Segment {
+    Literal(&'static str),
+    /// `<name>`, matching any one segment
+    Param(&'static str),
+    /// `<name..>`, matching one or more trailing segments
+    Rest(&'static str),
+}
+
+/// A handler along with the requests it responds to, generated by `#[route]`.
+pub struct Route<D> {
+    /// The methods this route responds to; GET routes also respond to HEAD
+    methods: Vec<Method>,
+    template: &'static str,
+    segments: &'static [Segment],
+    handler: fn(&D, &Captures) -> responders::Response,
+}
+
+impl<D> Route<D> {
+    pub fn new(methods: &[Method], template: &'static str, segments: &'static [Segment],
+               handler: fn(&D, &Captures) -> responders::Response) -> Route<D> {
+        Route { methods: methods.to_vec(), template, segments, handler }
+    }
+
+    fn allows(&self, method: &Method) -> bool {
+        self.methods.contains(method) ||
+            (*method == Method::Head && self.methods.contains(&Method::Get))
+    }
+
+    /// Matches the route's template against a request's path components.
+    fn captures(&self, components: &[String]) -> Option<Captures> {
+        let mut captures = Captures { values: Vec::new() };
+        let mut components = components.iter();
+        for segment in self.segments {
+            match *segment {
+                Segment::Literal(literal) =>
+                    if components.next().map(String::as_str) != Some(literal) {
+                        return None;
+                    },
+                Segment::Param(name) => captures.values.push((name, components.next()?.clone())),
+                Segment::Rest(name) => {
+                    let rest: Vec<_> = components.by_ref().map(String::as_str).collect();
+                    if rest.is_empty() {
+                        return None;
+                    }
+                    captures.values.push((name, rest.join("/")));
+                },
+            }
+        }
+        if components.next().is_some() { None } else { Some(captures) }
+    }
+
+    /// Whether every path this route's template matches is also matched by `earlier`'s.
+    fn shadowed_by(&self, earlier: &Route<D>) -> bool {
+        let mut later = self.segments.iter();
+        for segment in earlier.segments {
+            match (segment, later.next()) {
+                (&Segment::Rest(_), Some(_)) => return true,
+                (&Segment::Param(_), Some(&Segment::Literal(_))) |
+                (&Segment::Param(_), Some(&Segment::Param(_))) => {},
+                (&Segment::Literal(a), Some(&Segment::Literal(b))) if a == b => {},
+                _ => return false,
+            }
+        }
+        later.next().is_none()
+    }
+}
+
+impl<D> fmt::Display for Route<D> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let methods: Vec<_> = self.methods.iter().map(|m| m.to_string()).collect();
+        write!(f, "{} {}", methods.join(","), self.template)
+    }
+}
+
+/// The path parameters of a matched route.
+#[derive(Debug)]
+pub struct Captures {
+    values: Vec<(&'static str, String)>,
+}
+
+impl Captures {
+    /// The named parameter, as matched. Panics if the route has no such parameter, which
+    /// `#[route]` rules out.
+    pub fn get(&self, name: &str) -> &str {
+        self.values.iter().find(|&&(n, _)| n == name).map(|(_, v)| v.as_str())
+            .unwrap_or_else(|| panic!("No path parameter named {}", name))
+    }
+
+    /// Parses the named parameter, failing with a 400 if it isn't a valid `T`. Panics if the
+    /// route has no such parameter.
+    pub fn parse<T: FromStr>(&self, name: &str) -> Result<T, RivetError>
+            where T::Err: fmt::Display {
+        let value = self.get(name);
+        value.parse().map_err(|e| RivetError::BadRequest(
+            format!("Invalid {} {:?}: {}", name, value, e)))
+    }
+}
+
+/// Converts the return value of a `#[route]` handler into a response. Strings are sent as plain
+/// text.
+pub trait IntoResponse {
+    fn into_response(self) -> responders::Response;
+}
+
+impl IntoResponse for String {
+    fn into_response(self) -> responders::Response { self.as_str().into_response() }
+}
+
+impl IntoResponse for &str {
+    fn into_response(self) -> responders::Response {
+        util::ResponseBuilder::ok().text(self).build()
+    }
+}
+
+impl IntoResponse for util::ResponseBuilder {
+    fn into_response(self) -> responders::Response { self.build() }
+}
+
+impl IntoResponse for ResponseBox {
+    fn into_response(self) -> responders::Response { Ok(self) }
+}
+
+impl<T: IntoResponse> IntoResponse for Result<T, RivetError> {
+    fn into_response(self) -> responders::Response { self.and_then(IntoResponse::into_response) }
+}
+
+/// Serves `#[route]` handlers, collected with `routes![...]`. The binder `D` is only built for
+/// requests that match a route.
+pub struct Routes<D> {
+    deps: DepsFactory<D>,
+    routes: Vec<Route<D>>,
+}
+
+impl<D> Routes<D> {
+    pub fn new(deps: DepsFactory<D>, routes: Vec<Route<D>>) -> Routes<D> {
+        Routes { deps, routes }
+    }
+
+    /// Calls the first route matching the method and path, like `pattern::Pattern`.
+    fn dispatch(&self, request: &request::Request, url_parts: &util::UrlParts)
+            -> responders::Response {
+        let method = request.method();
+        let mut allowed = Vec::new();
+        for route in &self.routes {
+            if let Some(captures) = route.captures(url_parts.path_components()) {
+                if route.allows(method) {
+                    let deps = (self.deps)(request, url_parts)?;
+                    return (route.handler)(&deps, &captures);
+                }
+                allowed.extend(route.methods.iter().cloned());
+            }
+        }
+        if allowed.is_empty() {
+            return Err(RivetError::NotFound("No matching route".into()));
+        }
+        responders::method_not_allowed(method, allowed)
+    }
+}
+
+impl<D> responders::Responder for Routes<D> {
+    fn handle(&self, request: &request::Request, url_parts: &util::UrlParts)
+            -> responders::Response {
+        self.dispatch(request, url_parts)
+    }
+
+    /// Reports routes that earlier routes leave unreachable.
+    fn diagnostics(&self) -> Vec<String> {
+        let mut problems = Vec::new();
+        for (i, route) in self.routes.iter().enumerate() {
+            let shadowing: Vec<_> = self.routes[..i].iter()
+                .filter(|earlier| route.shadowed_by(earlier))
+                .collect();
+            if route.methods.iter().all(|m| shadowing.iter().any(|earlier| earlier.allows(m))) {
+                let earlier: Vec<_> = shadowing.iter().map(|r| r.to_string()).collect();
+                problems.push(format!("route {} is unreachable, shadowed by {}",
+                                      route, earlier.join(" and ")));
+            }
+        }
+        problems
+    }
+}
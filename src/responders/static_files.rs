@@ -0,0 +1,539 @@
+// Copyright 2017 Google LLC, Matthew Vilim
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//      http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use error::RivetError;
+use httpdate;
+use request;
+use responders;
+use std::ffi::OsString;
+use std::fs;
+use std::io::{self, Read, Seek, SeekFrom};
+use std::path::{Component, Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+use tiny_http::Method;
+use util;
+use util::{RedirectStatus, ResponseBuilder};
+
+/// Precompressed variants of a file, in order of preference, as (content coding, file extension)
+const ENCODINGS: &[(&str, &str)] = &[("br", "br"), ("gzip", "gz")];
+
+/// Serves the files under a root directory; e.g. mounted at `/static`, a request for
+/// `/static/css/site.css` is answered with `<root>/css/site.css`.
+///
+/// Files are sent with a `Content-Type` based on their extension, and support conditional requests
+/// (via `ETag` and `Last-Modified`) and single byte-range requests. If the client accepts it, a
+/// precompressed `<file>.br` or `<file>.gz` next to the file is sent in its place. Directories are
+/// served by their `index.html` or, if enabled, a listing of their contents.
+///
+/// Nothing outside the root is served, even if it's reachable via a symlink inside the root.
+pub struct StaticFiles {
+    /// Canonicalized, so resolved paths can be checked against it
+    root: PathBuf,
+    listing: bool,
+}
+
+impl StaticFiles {
+    /// Fails if `root` isn't an existing directory.
+    pub fn new<P: AsRef<Path>>(root: P) -> io::Result<StaticFiles> {
+        let root = fs::canonicalize(root)?;
+        if !root.is_dir() {
+            return Err(io::Error::other(format!("{} is not a directory", root.display())));
+        }
+        Ok(StaticFiles { root, listing: false })
+    }
+
+    /// Whether to list the contents of directories that don't have an `index.html`.
+    pub fn listing(mut self, listing: bool) -> StaticFiles {
+        self.listing = listing;
+        self
+    }
+
+    /// Maps decoded URL path components to an existing path under the root.
+    fn resolve(&self, components: &[String]) -> Result<PathBuf, RivetError> {
+        let mut path = self.root.clone();
+        for component in components {
+            // `UrlParts` already resolves dot segments, but a decoded component can still contain
+            // a separator (from `%2F`), or on Windows a drive prefix, which would escape the root
+            let mut parts = Path::new(component).components();
+            match (parts.next(), parts.next()) {
+                (Some(Component::Normal(_)), None) => path.push(component),
+                _ => return Err(not_found()),
+            }
+        }
+        self.contained(&path).ok_or_else(not_found)
+    }
+
+    /// The real location of `path`, if it exists and is under the root once symlinks are resolved.
+    fn contained(&self, path: &Path) -> Option<PathBuf> {
+        fs::canonicalize(path).ok().filter(|real| real.starts_with(&self.root))
+    }
+
+    /// Picks the file to send for `path`: the most preferred precompressed variant the client
+    /// accepts, or the file itself. Also returns whether any variants exist, in which case the
+    /// response depends on `Accept-Encoding`.
+    fn select_variant(&self, request: &request::Request, path: &Path)
+            -> (PathBuf, Option<&'static str>, bool) {
+        let accept_encoding = request.header("Accept-Encoding");
+        let mut selected = None;
+        let mut has_variants = false;
+        for &(encoding, extension) in ENCODINGS {
+            let mut variant = OsString::from(path.as_os_str());
+            variant.push(".");
+            variant.push(extension);
+            if let Some(variant) = self.contained(Path::new(&variant)).filter(|v| v.is_file()) {
+                has_variants = true;
+                if selected.is_none() && accepts_encoding(accept_encoding, encoding) {
+                    selected = Some((variant, encoding));
+                }
+            }
+        }
+        match selected {
+            Some((variant, encoding)) => (variant, Some(encoding), true),
+            None => (path.to_path_buf(), None, has_variants),
+        }
+    }
+
+    fn serve_file(&self, request: &request::Request, path: &Path) -> responders::Response {
+        let (file_path, encoding, has_variants) = self.select_variant(request, path);
+        let mut file = fs::File::open(&file_path).map_err(io_error)?;
+        let metadata = file.metadata().map_err(io_error)?;
+        let length = metadata.len();
+        let modified = metadata.modified().ok();
+        let etag = etag(length, modified, encoding);
+
+        let mut response = ResponseBuilder::ok()
+            .content_type(mime_type(path))
+            .etag(&etag)
+            .header("Accept-Ranges", "bytes");
+        if let Some(modified) = modified {
+            response = response.last_modified(modified);
+        }
+        if let Some(encoding) = encoding {
+            response = response.header("Content-Encoding", encoding);
+        }
+        if has_variants {
+            response = response.header("Vary", "Accept-Encoding");
+        }
+
+        if not_modified(request, &etag, modified) {
+            // No body is sent, but the Content-Length should still be that of the file
+            return response.status(304).stream(io::empty(), Some(length as usize)).build();
+        }
+        match requested_range(request, length, &etag, modified) {
+            ByteRange::Full => response.file(file).build(),
+            ByteRange::Partial { start, end } => {
+                file.seek(SeekFrom::Start(start)).map_err(io_error)?;
+                let range_length = end - start + 1;
+                response.status(206)
+                    .header("Content-Range", &format!("bytes {}-{}/{}", start, end, length))
+                    .stream(file.take(range_length), Some(range_length as usize))
+                    .build()
+            },
+            ByteRange::Unsatisfiable => ResponseBuilder::new(416)
+                .header("Content-Range", &format!("bytes */{}", length))
+                .build(),
+        }
+    }
+
+    /// An HTML page linking to each entry in `dir`, which is served at `full_url`; `url` is
+    /// relative to the mount point.
+    fn list(&self, dir: &Path, full_url: &util::UrlParts, url: &util::UrlParts)
+            -> responders::Response {
+        let mut entries: Vec<(String, bool)> = fs::read_dir(dir).map_err(io_error)?
+            .filter_map(|entry| entry.ok())
+            .map(|entry| (entry.file_name().to_string_lossy().into_owned(),
+                          entry.file_type().map(|t| t.is_dir()).unwrap_or(false)))
+            .collect();
+        entries.sort();
+
+        let title = html_escape(&util::percent_decode(full_url.path()));
+        let mut links = Vec::new();
+        if !url.path_components().is_empty() {
+            links.push("<li><a href=\"../\">../</a></li>".to_string());
+        }
+        for (name, is_dir) in entries {
+            let slash = if is_dir { "/" } else { "" };
+            links.push(format!("<li><a href=\"{}{}\">{}{}</a></li>",
+                               util::percent_encode(&name), slash, html_escape(&name), slash));
+        }
+        ResponseBuilder::ok()
+            .html(&format!("<h1>Index of {}</h1>\n<ul>\n{}\n</ul>", title, links.join("\n")))
+            .build()
+    }
+}
+
+impl responders::Responder for StaticFiles {
+    fn handle(&self, request: &request::Request, url: &util::UrlParts) -> responders::Response {
+        let method = request.method();
+        if *method != Method::Get && *method != Method::Head {
+            return Err(RivetError::MethodNotAllowed { allowed: vec![Method::Get, Method::Head] });
+        }
+
+        let path = self.resolve(url.path_components())?;
+        if !path.is_dir() {
+            return self.serve_file(request, &path);
+        }
+        // Relative links from a directory only resolve correctly with a trailing slash. `url` is
+        // relative to the mount point, which drops the slash when the root itself is requested,
+        // so check the full request target.
+        let full_url = util::UrlParts::parse(request.url())?;
+        if !full_url.path().ends_with('/') {
+            let name = full_url.path_components().last().map(|c| util::percent_encode(c));
+            let location = format!("{}/", name.unwrap_or_default());
+            return ResponseBuilder::redirect(RedirectStatus::MovedPermanently, &location).build();
+        }
+        match self.contained(&path.join("index.html")).filter(|index| index.is_file()) {
+            Some(index) => self.serve_file(request, &index),
+            None if self.listing => self.list(&path, &full_url, url),
+            None => Err(not_found()),
+        }
+    }
+}
+
+fn not_found() -> RivetError {
+    RivetError::NotFound("File not found".into())
+}
+
+fn io_error(error: io::Error) -> RivetError {
+    match error.kind() {
+        io::ErrorKind::NotFound => not_found(),
+        io::ErrorKind::PermissionDenied => RivetError::Forbidden("Permission denied".into()),
+        _ => RivetError::Internal(format!("Failed to read file: {}", error)),
+    }
+}
+
+/// The `Content-Type` for a file, based on its extension.
+fn mime_type(path: &Path) -> &'static str {
+    let extension = path.extension().and_then(|e| e.to_str()).unwrap_or("").to_ascii_lowercase();
+    match extension.as_ref() {
+        "html" | "htm" => "text/html; charset=UTF-8",
+        "css" => "text/css; charset=UTF-8",
+        "js" | "mjs" => "text/javascript; charset=UTF-8",
+        "txt" => "text/plain; charset=UTF-8",
+        "md" => "text/markdown; charset=UTF-8",
+        "csv" => "text/csv; charset=UTF-8",
+        "xml" => "application/xml",
+        "json" => "application/json",
+        "wasm" => "application/wasm",
+        "pdf" => "application/pdf",
+        "zip" => "application/zip",
+        "gz" => "application/gzip",
+        "svg" => "image/svg+xml",
+        "png" => "image/png",
+        "jpg" | "jpeg" => "image/jpeg",
+        "gif" => "image/gif",
+        "webp" => "image/webp",
+        "ico" => "image/x-icon",
+        "woff" => "font/woff",
+        "woff2" => "font/woff2",
+        "ttf" => "font/ttf",
+        "mp3" => "audio/mpeg",
+        "mp4" => "video/mp4",
+        "webm" => "video/webm",
+        _ => "application/octet-stream",
+    }
+}
+
+/// Whether an `Accept-Encoding` header permits the given content coding, i.e. lists it (or `*`)
+/// without `q=0`.
+fn accepts_encoding(accept_encoding: Option<&str>, encoding: &str) -> bool {
+    let mut wildcard = None;
+    for item in accept_encoding.unwrap_or("").split(',') {
+        let mut params = item.split(';');
+        let name = params.next().unwrap_or("").trim();
+        let quality = params.filter_map(|p| p.trim().strip_prefix("q="))
+            .next().and_then(|q| q.trim().parse::<f32>().ok()).unwrap_or(1.0);
+        if name.eq_ignore_ascii_case(encoding) {
+            return quality > 0.0;
+        } else if name == "*" {
+            wildcard = Some(quality > 0.0);
+        }
+    }
+    wildcard.unwrap_or(false)
+}
+
+/// A strong validator derived from the file's size and modification time, plus the encoding so
+/// each variant has a distinct tag.
+fn etag(length: u64, modified: Option<SystemTime>, encoding: Option<&str>) -> String {
+    let modified = modified.and_then(|m| m.duration_since(UNIX_EPOCH).ok())
+        .map(|d| d.as_nanos()).unwrap_or(0);
+    match encoding {
+        Some(encoding) => format!("\"{:x}-{:x}-{}\"", length, modified, encoding),
+        None => format!("\"{:x}-{:x}\"", length, modified),
+    }
+}
+
+/// Whether a conditional GET can be answered with a 304. `If-None-Match` takes precedence over
+/// `If-Modified-Since`, as in RFC 7232.
+fn not_modified(request: &request::Request, etag: &str, modified: Option<SystemTime>) -> bool {
+    if let Some(if_none_match) = request.header("If-None-Match") {
+        // Weak comparison; a W/ prefix is ignored
+        let etag = etag.trim_start_matches("W/");
+        return if_none_match.split(',').map(str::trim)
+            .any(|tag| tag == "*" || tag.trim_start_matches("W/") == etag);
+    }
+    let since = request.header("If-Modified-Since")
+        .and_then(|since| httpdate::parse_http_date(since).ok());
+    match (since, modified) {
+        // HTTP dates only have second precision
+        (Some(since), Some(modified)) => unix_seconds(modified) <= unix_seconds(since),
+        _ => false,
+    }
+}
+
+fn unix_seconds(time: SystemTime) -> u64 {
+    time.duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0)
+}
+
+#[derive(Debug, PartialEq)]
+enum ByteRange {
+    Full,
+    /// Inclusive of `end`, as in a `Content-Range` header
+    Partial { start: u64, end: u64 },
+    Unsatisfiable,
+}
+
+/// The part of a `length`-byte file the request asks for. Only single ranges are supported; a
+/// request for several ranges, or a `Range` header we can't parse, gets the whole file.
+fn requested_range(request: &request::Request, length: u64, etag: &str,
+                   modified: Option<SystemTime>) -> ByteRange {
+    let range = match request.header("Range") {
+        Some(range) => range,
+        None => return ByteRange::Full,
+    };
+    // If-Range asks for the range only if the file hasn't changed, and the whole file otherwise
+    if let Some(if_range) = request.header("If-Range") {
+        let unchanged = if if_range.starts_with('"') {
+            if_range == etag
+        } else {
+            modified.map(httpdate::fmt_http_date).as_deref() == Some(if_range)
+        };
+        if !unchanged {
+            return ByteRange::Full;
+        }
+    }
+    parse_range(range, length)
+}
+
+fn parse_range(range: &str, length: u64) -> ByteRange {
+    let spec = match range.trim().strip_prefix("bytes=") {
+        Some(spec) if !spec.contains(',') => spec.trim(),
+        _ => return ByteRange::Full,
+    };
+    let (first, last) = match spec.find('-') {
+        Some(index) => (&spec[..index], &spec[index + 1..]),
+        None => return ByteRange::Full,
+    };
+    let (first, last) = match (first.parse::<u64>().ok(), last.parse::<u64>().ok()) {
+        (None, None) => return ByteRange::Full,
+        (Some(first), _) if first >= length => return ByteRange::Unsatisfiable,
+        (Some(first), Some(last)) if last < first => return ByteRange::Full,
+        (Some(first), Some(last)) => (first, last.min(length - 1)),
+        (Some(first), None) if last.is_empty() => (first, length - 1),
+        (Some(_), None) => return ByteRange::Full,
+        // A suffix range, i.e. the last `suffix` bytes
+        (None, Some(suffix)) if first.is_empty() => {
+            if length == 0 || suffix == 0 {
+                return ByteRange::Unsatisfiable;
+            }
+            (length.saturating_sub(suffix), length - 1)
+        },
+        (None, Some(_)) => return ByteRange::Full,
+    };
+    ByteRange::Partial { start: first, end: last }
+}
+
+fn html_escape(s: &str) -> String {
+    s.replace('&', "&amp;").replace('<', "&lt;").replace('>', "&gt;").replace('"', "&quot;")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use responders::testing::{self, body, header};
+    use std::env;
+    use std::process;
+    use tiny_http;
+
+    /// A directory of test files, deleted when dropped.
+    struct TempDir(PathBuf);
+
+    impl TempDir {
+        fn new(name: &str) -> TempDir {
+            let dir = env::temp_dir().join(format!("rivet-static-{}-{}", process::id(), name));
+            let _ = fs::remove_dir_all(&dir);
+            fs::create_dir_all(dir.join("docs")).unwrap();
+            fs::write(dir.join("hello.txt"), "Hello, world!").unwrap();
+            fs::write(dir.join("docs/index.html"), "<p>Docs</p>").unwrap();
+            fs::create_dir(dir.join("empty")).unwrap();
+            TempDir(dir)
+        }
+    }
+
+    impl Drop for TempDir {
+        fn drop(&mut self) {
+            let _ = fs::remove_dir_all(&self.0);
+        }
+    }
+
+    fn get(files: &StaticFiles, path: &str, headers: &[(&str, &str)])
+            -> Result<tiny_http::ResponseBox, RivetError> {
+        let mut test_request = tiny_http::TestRequest::new().with_path(path);
+        for &(name, value) in headers {
+            test_request = test_request.with_header(
+                tiny_http::Header::from_bytes(name.as_bytes(), value.as_bytes()).unwrap());
+        }
+        testing::handle(files, test_request, request::Body::default())
+    }
+
+    fn status(result: Result<tiny_http::ResponseBox, RivetError>) -> u16 {
+        match result {
+            Ok(response) => response.status_code().0,
+            Err(error) => error.status_code(),
+        }
+    }
+
+    #[test]
+    fn serves_files() {
+        let dir = TempDir::new("files");
+        let files = StaticFiles::new(&dir.0).unwrap();
+        let response = get(&files, "/hello.txt", &[]).unwrap();
+        assert_eq!(response.status_code().0, 200);
+        assert_eq!(header(&response, "Content-Type").unwrap(), "text/plain; charset=UTF-8");
+        assert_eq!(response.data_length(), Some(13));
+        assert_eq!(body(response), "Hello, world!");
+
+        assert_eq!(status(get(&files, "/missing.txt", &[])), 404);
+        assert!(StaticFiles::new(dir.0.join("hello.txt")).is_err());
+    }
+
+    #[test]
+    fn path_traversal() {
+        let dir = TempDir::new("traversal");
+        let files = StaticFiles::new(dir.0.join("docs")).unwrap();
+        assert_eq!(status(get(&files, "/../hello.txt", &[])), 404);
+        assert_eq!(status(get(&files, "/%2E%2E/hello.txt", &[])), 404);
+        assert!(files.resolve(&["..".into(), "hello.txt".into()]).is_err());
+        assert!(files.resolve(&["../hello.txt".into()]).is_err());
+        assert!(files.resolve(&["index.html".into()]).is_ok());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn symlinks_outside_root() {
+        let dir = TempDir::new("symlinks");
+        ::std::os::unix::fs::symlink(dir.0.join("hello.txt"), dir.0.join("docs/link.txt")).unwrap();
+        let files = StaticFiles::new(dir.0.join("docs")).unwrap();
+        assert_eq!(status(get(&files, "/link.txt", &[])), 404);
+    }
+
+    #[test]
+    fn conditional_get() {
+        let dir = TempDir::new("conditional");
+        let files = StaticFiles::new(&dir.0).unwrap();
+        let response = get(&files, "/hello.txt", &[]).unwrap();
+        let etag = header(&response, "ETag").unwrap();
+        let last_modified = header(&response, "Last-Modified").unwrap();
+
+        let response = get(&files, "/hello.txt", &[("If-None-Match", &etag)]).unwrap();
+        assert_eq!(response.status_code().0, 304);
+        assert_eq!(response.data_length(), Some(13));
+        assert_eq!(status(get(&files, "/hello.txt", &[("If-None-Match", "\"other\"")])), 200);
+        assert_eq!(status(get(&files, "/hello.txt", &[("If-Modified-Since", &last_modified)])),
+                   304);
+        let before = "Thu, 01 Jan 1970 00:00:00 GMT";
+        assert_eq!(status(get(&files, "/hello.txt", &[("If-Modified-Since", before)])), 200);
+        // If-None-Match takes precedence
+        assert_eq!(status(get(&files, "/hello.txt",
+                              &[("If-None-Match", "\"other\""),
+                                ("If-Modified-Since", &last_modified)])), 200);
+    }
+
+    #[test]
+    fn ranges() {
+        let dir = TempDir::new("ranges");
+        let files = StaticFiles::new(&dir.0).unwrap();
+        let response = get(&files, "/hello.txt", &[("Range", "bytes=7-11")]).unwrap();
+        assert_eq!(response.status_code().0, 206);
+        assert_eq!(header(&response, "Content-Range").unwrap(), "bytes 7-11/13");
+        assert_eq!(body(response), "world");
+
+        let response = get(&files, "/hello.txt", &[("Range", "bytes=-1")]).unwrap();
+        assert_eq!(body(response), "!");
+        let response = get(&files, "/hello.txt", &[("Range", "bytes=20-")]).unwrap();
+        assert_eq!(response.status_code().0, 416);
+        assert_eq!(header(&response, "Content-Range").unwrap(), "bytes */13");
+        let stale = [("Range", "bytes=0-1"), ("If-Range", "\"stale\"")];
+        assert_eq!(status(get(&files, "/hello.txt", &stale)), 200);
+
+        assert_eq!(parse_range("bytes=5-", 10), ByteRange::Partial { start: 5, end: 9 });
+        assert_eq!(parse_range("bytes=0-100", 10), ByteRange::Partial { start: 0, end: 9 });
+        assert_eq!(parse_range("bytes=-20", 10), ByteRange::Partial { start: 0, end: 9 });
+        assert_eq!(parse_range("bytes=0-1,3-4", 10), ByteRange::Full);
+        assert_eq!(parse_range("bytes=3-1", 10), ByteRange::Full);
+        assert_eq!(parse_range("lines=1-2", 10), ByteRange::Full);
+        assert_eq!(parse_range("bytes=0-", 0), ByteRange::Unsatisfiable);
+        assert_eq!(parse_range("bytes=-0", 10), ByteRange::Unsatisfiable);
+    }
+
+    #[test]
+    fn precompressed() {
+        let dir = TempDir::new("precompressed");
+        fs::write(dir.0.join("hello.txt.gz"), "gzipped").unwrap();
+        let files = StaticFiles::new(&dir.0).unwrap();
+
+        let response = get(&files, "/hello.txt", &[("Accept-Encoding", "br, gzip")]).unwrap();
+        assert_eq!(header(&response, "Content-Encoding").unwrap(), "gzip");
+        assert_eq!(header(&response, "Content-Type").unwrap(), "text/plain; charset=UTF-8");
+        assert_eq!(header(&response, "Vary").unwrap(), "Accept-Encoding");
+        assert_eq!(body(response), "gzipped");
+
+        let response = get(&files, "/hello.txt", &[("Accept-Encoding", "gzip;q=0")]).unwrap();
+        assert_eq!(header(&response, "Content-Encoding"), None);
+        assert_eq!(body(response), "Hello, world!");
+    }
+
+    #[test]
+    fn directories() {
+        let dir = TempDir::new("directories");
+        let files = StaticFiles::new(&dir.0).unwrap();
+        let response = get(&files, "/docs", &[]).unwrap();
+        assert_eq!(response.status_code().0, 301);
+        assert_eq!(header(&response, "Location").unwrap(), "docs/");
+        assert_eq!(body(get(&files, "/docs/", &[]).unwrap()), "<p>Docs</p>");
+        assert_eq!(status(get(&files, "/empty/", &[])), 404);
+
+        let files = files.listing(true);
+        assert_eq!(status(get(&files, "/empty/", &[])), 200);
+        let listing = body(get(&files, "/", &[]).unwrap());
+        assert!(listing.contains("<a href=\"docs/\">docs/</a>"));
+        assert!(listing.contains("<a href=\"hello.txt\">hello.txt</a>"));
+        assert!(!listing.contains("../"));
+    }
+
+    #[test]
+    fn mime_types() {
+        assert_eq!(mime_type(Path::new("a/index.HTML")), "text/html; charset=UTF-8");
+        assert_eq!(mime_type(Path::new("logo.svg")), "image/svg+xml");
+        assert_eq!(mime_type(Path::new("Makefile")), "application/octet-stream");
+    }
+
+    #[test]
+    fn accept_encoding() {
+        assert!(accepts_encoding(Some("gzip, deflate"), "gzip"));
+        assert!(accepts_encoding(Some("*"), "br"));
+        assert!(!accepts_encoding(Some("*, br;q=0"), "br"));
+        assert!(!accepts_encoding(None, "gzip"));
+    }
+}
@@ -12,9 +12,8 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
+use request;
 use responders;
-use std::collections::HashMap;
-use tiny_http;
 use util;
 
 /// Stringly-typed responder, treats URLs as strings, application logic must do parsing
@@ -22,14 +21,17 @@ pub struct Stringly {
 }
 
 impl responders::Responder for Stringly {
-    fn handle(&self, request: &tiny_http::Request) -> tiny_http::ResponseBox {
-        let url_parts = util::strip_url_prefix(request.url(), "/stringly");
-
-        let response = respond(url_parts.path_components(), url_parts.query());
-        util::success(&response)
+    fn handle(&self, request: &request::Request, url_parts: &util::UrlParts)
+            -> responders::Response {
+        // The form is only shown if there is one, so other bodies aren't an error
+        let body = request.body();
+        let form = if body.is_form() { body.form()? } else { util::Query::default() };
+        let response = respond(url_parts.path_components(), url_parts.query(), &form);
+        util::ResponseBuilder::ok().text(&response).build()
     }
 }
 
-fn respond(url_components: &Vec<String>, url_params: &HashMap<String, String>) -> String {
-    format!("stringly!\nURL parts: |{}|\nQuery args: {:?}", url_components.join("|"), url_params)
+fn respond(url_components: &[String], url_params: &util::Query, form: &util::Query) -> String {
+    format!("stringly!\nURL parts: |{}|\nQuery args: {:?}\nForm args: {:?}",
+            url_components.join("|"), url_params, form)
 }
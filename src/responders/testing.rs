@@ -0,0 +1,52 @@
+// Copyright 2017 Google LLC, Matthew Vilim
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//      http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Helpers for tests that send requests to responders and inspect their responses.
+
+use error::RivetError;
+use request;
+use responders;
+use std::io::Read;
+use tiny_http;
+use util;
+
+/// Calls `responder` with `request`, whose path is relative to where the responder is mounted.
+pub fn handle(responder: &dyn responders::Responder, request: tiny_http::TestRequest,
+              body: request::Body) -> responders::Response {
+    let raw: tiny_http::Request = request.into();
+    let url_parts = util::UrlParts::new(raw.url());
+    responder.handle(&request::Request::new(&raw, body), &url_parts)
+}
+
+/// Calls `responder` with a GET request for `url`.
+pub fn get(responder: &dyn responders::Responder, url: &str) -> responders::Response {
+    handle(responder, tiny_http::TestRequest::new().with_path(url), request::Body::default())
+}
+
+/// The body of a successful response, as text.
+pub fn text(response: responders::Response) -> Result<String, RivetError> {
+    response.map(body)
+}
+
+/// The body of a response, as text.
+pub fn body(response: tiny_http::ResponseBox) -> String {
+    let mut body = String::new();
+    response.into_reader().read_to_string(&mut body).unwrap();
+    body
+}
+
+/// The value of the response's `name` header, if it has one.
+pub fn header(response: &tiny_http::ResponseBox, name: &'static str) -> Option<String> {
+    response.headers().iter().find(|h| h.field.equiv(name)).map(|h| h.value.to_string())
+}
@@ -12,10 +12,10 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
+use request;
 use responders;
 use std::collections::HashMap;
 use std::any::Any;
-use tiny_http;
 use util;
 
 /// Use traits to expose a Map<_, Any> safely
@@ -23,13 +23,12 @@ pub struct Traits {
 }
 
 impl responders::Responder for Traits {
-    fn handle(&self, request: &tiny_http::Request) -> tiny_http::ResponseBox {
-        let url_parts = util::strip_url_prefix(request.url(), "/traits");
-
+    fn handle(&self, _request: &request::Request, url_parts: &util::UrlParts)
+            -> responders::Response {
         let mut di_map = DIMap::new();
-        PathParts::put(&mut di_map, url_parts.path_components);
-        UrlParams::put(&mut di_map, url_parts.query);
-        util::success(&dispatch(&di_map, &di_map))
+        PathParts::put(&mut di_map, url_parts.path_components.clone());
+        UrlParams::put(&mut di_map, url_parts.query.clone());
+        util::ResponseBuilder::ok().text(&dispatch(&di_map, &di_map)).build()
     }
 }
 
@@ -38,7 +37,7 @@ fn dispatch<P: PathParts, Q: UrlParams>(parts: &P, query: &Q) -> String {
 }
 
 struct DIMap {
-    store: HashMap<String, Box<Any>>
+    store: HashMap<String, Box<dyn Any>>
 }
 
 impl DIMap {
@@ -60,23 +59,23 @@ impl PathParts for DIMap {
         self.store.get("PathParts").unwrap().downcast_ref::<Vec<String>>().unwrap()
     }
     fn put(&mut self, value: Vec<String>) {
-        self.store.insert("PathParts".into(), Box::new(value) as Box<Any>);
+        self.store.insert("PathParts".into(), Box::new(value) as Box<dyn Any>);
     }
 }
 
 trait UrlParams {
-    fn get(&self) -> &HashMap<String, String>;
+    fn get(&self) -> &util::Query;
 
-    fn put(&mut self, value: HashMap<String, String>);
+    fn put(&mut self, value: util::Query);
 }
 
 
 impl UrlParams for DIMap {
-    fn get(&self) -> &HashMap<String, String> {
-        self.store.get("UrlParams").unwrap().downcast_ref::<HashMap<String, String>>().unwrap()
+    fn get(&self) -> &util::Query {
+        self.store.get("UrlParams").unwrap().downcast_ref::<util::Query>().unwrap()
     }
 
-    fn put(&mut self, value: HashMap<String, String>) {
-        self.store.insert("UrlParams".into(), Box::new(value) as Box<Any>);
+    fn put(&mut self, value: util::Query) {
+        self.store.insert("UrlParams".into(), Box::new(value) as Box<dyn Any>);
     }
 }
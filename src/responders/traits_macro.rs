@@ -12,61 +12,85 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
+use error::RivetError;
+use inject::Dep;
+use request;
 use responders;
-use std::collections::HashMap;
-use tiny_http;
+use serde_json;
 use util;
 
 /// Same pattern as traits.rs, but using macros to reduce boilerplate
 pub struct TraitsMacro {
 }
 
-binder!(DI);
-binding!(DI, UrlParts, util::UrlParts);
+bindings!(DI, DIBuilder {
+    url_parts: UrlParts => util::UrlParts,
+    form_params: FormParams => util::Query,
+    json_body: JsonBody => serde_json::Value,
+});
 provider!(DI, PathParts, Vec<String>, UrlParts, |d: &'a UrlParts| d.get().path_components());
-provider!(DI, UrlParams, HashMap<String, String>, UrlParts, |d: &'a UrlParts| d.get().query());
+provider!(DI, UrlParams, util::Query, UrlParts, |d: &'a UrlParts| d.get().query());
 
 impl responders::Responder for TraitsMacro {
-    fn handle(&self, request: &tiny_http::Request) -> tiny_http::ResponseBox {
-        let url_parts = util::strip_url_prefix(request.url(), "/traits_macro");
+    fn handle(&self, request: &request::Request, url_parts: &util::UrlParts)
+            -> responders::Response {
+        let callback = dispatcher(url_parts);
 
-        let callback = dispatcher(&url_parts);
+        // Every binding is supplied up front, so bodies of another type bind as empty values
+        // rather than failing requests that don't use them
+        let body = request.body();
+        let form = if body.is_form() { body.form()? } else { util::Query::default() };
+        let json = if body.is_json() { body.json()? } else { serde_json::Value::Null };
 
-        let mut deps = DI::new();
-        bind!(deps, UrlParts, url_parts);
+        // Leaving out any of these is a compile error, rather than a panic in the callback
+        let deps = DIBuilder::new()
+            .url_parts(url_parts.clone())
+            .form_params(form)
+            .json_body(json)
+            .build();
 
         callback(&deps)
     }
 }
 
-fn dispatcher(url_parts: &util::UrlParts) -> Box<Fn(&DI) -> tiny_http::ResponseBox> {
+fn dispatcher(url_parts: &util::UrlParts) -> Box<dyn Fn(&DI) -> responders::Response> {
     match url_parts.path_components().first() {
         Some(path) => match path.as_ref() {
-            "path" => inject_http_success!(DI, paths_only, 1),
-            "query" => inject_http_success!(DI, query_only, 1),
-            "both" => inject_http_success!(DI, both, 2),
-            "all" => inject_http_success!(DI, all, 3),
-            _ => Box::new(|_deps|util::fail404("Not found")),
+            "path" => inject_http_success!(DI, paths_only),
+            "query" => inject_http_success!(DI, query_only),
+            "both" => inject_http_success!(DI, both),
+            "all" => inject_http_success!(DI, all),
+            "form" => inject_http_success!(DI, form),
+            "json" => inject_http_success!(DI, json),
+            _ => Box::new(|_deps| Err(RivetError::NotFound("Not found".into()))),
         }
-        _ => inject_http_success!(DI, root, 0),
+        _ => inject_http_success!(DI, root),
     }
 }
 
-fn root() -> String { "Try /path, /query, /both, or /all".into() }
+fn root() -> String { "Try /path, /query, /both, /all, or POST to /form or /json".into() }
 
 
-fn paths_only<P: PathParts>(paths: &P) -> String {
-    format!("Paths Only! {:?}", paths.get())
+fn paths_only(paths: Dep<dyn PathParts>) -> String {
+    format!("Paths Only! {:?}", paths)
 }
 
-fn query_only<Q: UrlParams>(query: &Q) -> String {
-    format!("Query Only! {:?}", query.get())
+fn query_only(query: Dep<dyn UrlParams>) -> String {
+    format!("Query Only! {:?}", query)
 }
 
-fn both<P: PathParts, Q: UrlParams>(parts: &P, query: &Q) -> String {
-    format!("Paths: {:?} and Query: {:?}", parts.get(), query.get())
+fn both(parts: Dep<dyn PathParts>, query: Dep<dyn UrlParams>) -> String {
+    format!("Paths: {:?} and Query: {:?}", parts, query)
 }
 
-fn all<U: UrlParts, P: PathParts, Q: UrlParams>(url: &U, parts: &P, query: &Q) -> String {
-    format!("URL: {}, Paths: {:?}, and Query: {:?}", url.get().path(), parts.get(), query.get())
+fn all(url: Dep<dyn UrlParts>, parts: Dep<dyn PathParts>, query: Dep<dyn UrlParams>) -> String {
+    format!("URL: {}, Paths: {:?}, and Query: {:?}", url.path(), parts, query)
+}
+
+fn form(form: Dep<dyn FormParams>) -> String {
+    format!("Form! {:?}", form)
+}
+
+fn json(json: Dep<dyn JsonBody>) -> String {
+    format!("JSON! {}", *json)
 }
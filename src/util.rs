@@ -12,80 +12,543 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
+//! Common utilities that may be used across responders
+
+use error::RivetError;
+use httpdate;
 use regex;
-use std::collections::HashMap;
+use serde::Serialize;
+use serde_json;
+use std::fmt;
+use std::fs;
+use std::io::{self, Cursor, Read};
+use std::str::FromStr;
+use std::time::{Duration, SystemTime};
 use tiny_http;
 
-/// Common utilities that may be used across responders
+/// A redirect status, for `ResponseBuilder::redirect()`.
+#[allow(dead_code)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RedirectStatus {
+    /// 301; clients may change the method to GET
+    MovedPermanently = 301,
+    /// 302; clients may change the method to GET
+    Found = 302,
+    /// 307; clients must repeat the request with the same method and body
+    TemporaryRedirect = 307,
+    /// 308; clients must repeat the request with the same method and body
+    PermanentRedirect = 308,
+}
+
+/// A caching policy, for `ResponseBuilder::cache_control()`.
+#[allow(dead_code)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CacheControl {
+    /// Caches must not store the response at all
+    NoStore,
+    /// Caches may store the response, but must revalidate it before each use
+    NoCache,
+    /// Any cache may reuse the response for this long
+    Public(Duration),
+    /// Only the client's own cache may reuse the response, for this long
+    Private(Duration),
+}
+
+impl fmt::Display for CacheControl {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            CacheControl::NoStore => f.write_str("no-store"),
+            CacheControl::NoCache => f.write_str("no-cache"),
+            CacheControl::Public(max_age) => write!(f, "public, max-age={}", max_age.as_secs()),
+            CacheControl::Private(max_age) => write!(f, "private, max-age={}", max_age.as_secs()),
+        }
+    }
+}
+
+/// The `SameSite` attribute of a `Cookie`.
+#[allow(dead_code)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SameSite {
+    Strict,
+    Lax,
+    None,
+}
+
+/// A cookie to set with `ResponseBuilder::cookie()`, rendered as a `Set-Cookie` header. Values
+/// that may contain characters not allowed by RFC 6265, such as `;`, `,` or spaces, should be
+/// percent-encoded by the caller.
+#[allow(dead_code)]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Cookie {
+    name: String,
+    value: String,
+    path: Option<String>,
+    max_age: Option<u64>,
+    http_only: bool,
+    secure: bool,
+    same_site: Option<SameSite>,
+}
+
+#[allow(dead_code)]
+impl Cookie {
+    pub fn new(name: &str, value: &str) -> Cookie {
+        Cookie {
+            name: name.into(), value: value.into(), path: None, max_age: None, http_only: false,
+            secure: false, same_site: None,
+        }
+    }
+
+    pub fn path(mut self, path: &str) -> Cookie { self.path = Some(path.into()); self }
+
+    /// How long, in seconds, the client should keep the cookie; 0 deletes it.
+    pub fn max_age(mut self, seconds: u64) -> Cookie { self.max_age = Some(seconds); self }
+
+    pub fn http_only(mut self) -> Cookie { self.http_only = true; self }
+
+    pub fn secure(mut self) -> Cookie { self.secure = true; self }
 
-pub fn success(response: &str) -> tiny_http::ResponseBox {
-    tiny_http::Response::from_string(response).boxed()
+    pub fn same_site(mut self, same_site: SameSite) -> Cookie {
+        self.same_site = Some(same_site);
+        self
+    }
+
+    /// Checks the name is a token and the value and path can't end the attribute or header
+    /// they're written into (RFC 6265, section 4.1.1).
+    fn validate(&self) -> Result<(), String> {
+        let is_tchar = |c: char| c.is_ascii_alphanumeric() || "!#$%&'*+-.^_`|~".contains(c);
+        let is_cookie_octet = |c: char| c.is_ascii_graphic() && !"\",;\\".contains(c);
+        let is_path_char = |c: char| (c == ' ' || c.is_ascii_graphic()) && c != ';';
+        if self.name.is_empty() || !self.name.chars().all(is_tchar) {
+            return Err(format!("Invalid cookie name: {:?}", self.name));
+        }
+        if !self.value.chars().all(is_cookie_octet) {
+            return Err(format!("Invalid value for cookie {}: {:?}", self.name, self.value));
+        }
+        match self.path {
+            Some(ref path) if !path.chars().all(is_path_char) =>
+                Err(format!("Invalid path for cookie {}: {:?}", self.name, path)),
+            _ => Ok(()),
+        }
+    }
 }
 
-pub fn success_html(response: &str) -> tiny_http::ResponseBox {
-    tiny_http::Response::from_string(response)
-        .with_header("Content-type: text/html".parse::<tiny_http::Header>().unwrap()).boxed()
+impl fmt::Display for Cookie {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}={}", self.name, self.value)?;
+        if let Some(ref path) = self.path { write!(f, "; Path={}", path)?; }
+        if let Some(max_age) = self.max_age { write!(f, "; Max-Age={}", max_age)?; }
+        if self.http_only { f.write_str("; HttpOnly")?; }
+        if self.secure { f.write_str("; Secure")?; }
+        if let Some(same_site) = self.same_site { write!(f, "; SameSite={:?}", same_site)?; }
+        Ok(())
+    }
 }
 
-pub fn fail404(response: &str) -> tiny_http::ResponseBox {
-    tiny_http::Response::from_string(response)
-        .with_status_code(tiny_http::StatusCode::from(404)).boxed()
+enum ResponseBody {
+    Data(Vec<u8>),
+    Stream { reader: Box<dyn Read + Send>, length: Option<usize> },
+}
+
+/// Builds a response with any status, headers, and body, e.g.
+///
+/// ```ignore
+/// ResponseBuilder::ok().cookie(Cookie::new("seen", "1")).json(&value).build()
+/// ```
+///
+/// Invalid values, such as a header that isn't ASCII, are reported by `build()` as an `Internal`
+/// error rather than where they're set, so a chain of calls doesn't need to check each step.
+pub struct ResponseBuilder {
+    status: u16,
+    headers: Vec<tiny_http::Header>,
+    body: ResponseBody,
+    error: Option<RivetError>,
+}
+
+#[allow(dead_code)]
+impl ResponseBuilder {
+    /// A response with the given status and an empty body.
+    pub fn new(status: u16) -> ResponseBuilder {
+        ResponseBuilder {
+            status, headers: Vec::new(), body: ResponseBody::Data(Vec::new()), error: None }
+    }
+
+    pub fn ok() -> ResponseBuilder { ResponseBuilder::new(200) }
+
+    /// A 204 response; any body is dropped by `build()`.
+    pub fn no_content() -> ResponseBuilder { ResponseBuilder::new(204) }
+
+    pub fn redirect(status: RedirectStatus, location: &str) -> ResponseBuilder {
+        ResponseBuilder::new(status as u16).location(location)
+    }
+
+    pub fn status(mut self, status: u16) -> ResponseBuilder {
+        self.status = status;
+        self
+    }
+
+    /// Adds a header; headers may be repeated.
+    pub fn header(mut self, name: &str, value: &str) -> ResponseBuilder {
+        match tiny_http::Header::from_bytes(name.as_bytes(), value.as_bytes()) {
+            Ok(header) => self.headers.push(header),
+            Err(()) => self.fail(format!("Invalid {} header: {:?}", name, value)),
+        }
+        self
+    }
+
+    /// Sets the `Content-Type` header, replacing any previous value.
+    pub fn content_type(self, content_type: &str) -> ResponseBuilder {
+        self.replace_header("Content-Type", content_type)
+    }
+
+    /// Sets the `Location` header, replacing any previous value.
+    pub fn location(self, location: &str) -> ResponseBuilder {
+        self.replace_header("Location", location)
+    }
+
+    /// Sets the `Cache-Control` header, replacing any previous value.
+    pub fn cache_control(self, cache_control: CacheControl) -> ResponseBuilder {
+        self.replace_header("Cache-Control", &cache_control.to_string())
+    }
+
+    /// Sets the `ETag` header, replacing any previous value. `etag` includes its quotes, e.g.
+    /// `"v1"` or (for a weak validator) `W/"v1"`; an invalid tag is reported by `build()`.
+    pub fn etag(mut self, etag: &str) -> ResponseBuilder {
+        let opaque = etag.strip_prefix("W/").unwrap_or(etag);
+        let valid = opaque.len() >= 2 && opaque.starts_with('"') && opaque.ends_with('"')
+            && opaque[1..opaque.len() - 1].chars().all(|c| c.is_ascii_graphic() && c != '"');
+        if !valid {
+            self.fail(format!("Invalid ETag: {:?}", etag));
+            return self;
+        }
+        self.replace_header("ETag", etag)
+    }
+
+    /// Sets the `Last-Modified` header, replacing any previous value.
+    pub fn last_modified(self, modified: SystemTime) -> ResponseBuilder {
+        self.replace_header("Last-Modified", &httpdate::fmt_http_date(modified))
+    }
+
+    fn replace_header(mut self, name: &'static str, value: &str) -> ResponseBuilder {
+        self.headers.retain(|h| !h.field.equiv(name));
+        self.header(name, value)
+    }
+
+    pub fn allow(mut self, methods: &[tiny_http::Method]) -> ResponseBuilder {
+        self.headers.push(allow_header(methods));
+        self
+    }
+
+    pub fn cookie(mut self, cookie: Cookie) -> ResponseBuilder {
+        match cookie.validate() {
+            Ok(()) => self.header("Set-Cookie", &cookie.to_string()),
+            Err(message) => {
+                self.fail(message);
+                self
+            },
+        }
+    }
+
+    pub fn text(self, body: &str) -> ResponseBuilder {
+        self.content_type("text/plain; charset=UTF-8").bytes(body.as_bytes().to_vec())
+    }
+
+    pub fn html(self, body: &str) -> ResponseBuilder {
+        self.content_type("text/html; charset=UTF-8").bytes(body.as_bytes().to_vec())
+    }
+
+    /// Serializes `value` as the JSON body.
+    pub fn json<T: Serialize>(mut self, value: &T) -> ResponseBuilder {
+        match serde_json::to_vec(value) {
+            Ok(json) => self.content_type("application/json").bytes(json),
+            Err(e) => {
+                self.fail(format!("Failed to serialize JSON response: {}", e));
+                self
+            },
+        }
+    }
+
+    /// Sets the body, without changing the `Content-Type`.
+    pub fn bytes(mut self, body: Vec<u8>) -> ResponseBuilder {
+        self.body = ResponseBody::Data(body);
+        self
+    }
+
+    /// Streams the body from `reader` as the response is sent, rather than buffering it. If the
+    /// length isn't known the response is sent with chunked encoding.
+    pub fn stream<R: Read + Send + 'static>(mut self, reader: R, length: Option<usize>)
+            -> ResponseBuilder {
+        self.body = ResponseBody::Stream { reader: Box::new(reader), length };
+        self
+    }
+
+    /// Streams the contents of `file` as the body.
+    pub fn file(self, file: fs::File) -> ResponseBuilder {
+        let length = file.metadata().ok().map(|m| m.len() as usize);
+        self.stream(file, length)
+    }
+
+    /// Builds the response. Responses that can't have a body - 1xx, 204 and 304 - are sent
+    /// without one, or any `Content-*` headers describing it; a 304 keeps the body's length,
+    /// which is that of the representation a 200 would have sent.
+    pub fn build(mut self) -> Result<tiny_http::ResponseBox, RivetError> {
+        if let Some(error) = self.error {
+            return Err(error);
+        }
+        let (reader, length): (Box<dyn Read + Send>, _) = match self.body {
+            ResponseBody::Data(data) => {
+                let length = data.len();
+                (Box::new(Cursor::new(data)), Some(length))
+            },
+            ResponseBody::Stream { reader, length } => (reader, length),
+        };
+        let (reader, length) = match self.status {
+            100..=199 | 204 => (Box::new(io::empty()) as Box<dyn Read + Send>, Some(0)),
+            304 => (Box::new(io::empty()) as Box<dyn Read + Send>, length),
+            _ => (reader, length),
+        };
+        if matches!(self.status, 100..=199 | 204 | 304) {
+            self.headers.retain(|h| !h.field.as_str().as_str().to_ascii_lowercase()
+                .starts_with("content-"));
+        }
+        Ok(tiny_http::Response::new(
+            tiny_http::StatusCode(self.status), self.headers, reader, length, None))
+    }
+
+    /// Records the first invalid value, to be returned by `build()`.
+    fn fail(&mut self, message: String) {
+        if self.error.is_none() {
+            self.error = Some(RivetError::Internal(message));
+        }
+    }
+}
+
+/// An `Allow` header listing the given methods, as sent with 405 responses and OPTIONS requests.
+pub fn allow_header(methods: &[tiny_http::Method]) -> tiny_http::Header {
+    let methods: Vec<_> = methods.iter().map(|m| m.to_string()).collect();
+    tiny_http::Header::from_bytes(&b"Allow"[..], methods.join(", "))
+        .expect("Methods are valid header values")
+}
+
+/// Returns the value of the first header with the given (case-insensitive) name, if any.
+pub fn header<'a>(request: &'a tiny_http::Request, name: &'static str) -> Option<&'a str> {
+    request.headers().iter().find(|h| h.field.equiv(name)).map(|h| h.value.as_str())
 }
 
 lazy_static! {
     static ref URL_SPLIT: regex::Regex = regex::Regex::new(r"^([^?]*)(?:\?(.*))?$").unwrap();
-    static ref PATH_SEGMENTS: regex::Regex = regex::Regex::new("/([^/]+)").unwrap();
-    static ref QUERY_SEGMENT: regex::Regex = regex::Regex::new("^([^=]*)(?:=(.*))?$").unwrap();
+    /// Matches `scheme://authority`, capturing whatever follows (the path and query, if any)
+    static ref ABSOLUTE_FORM: regex::Regex =
+        regex::Regex::new(r"^[A-Za-z][A-Za-z0-9+.-]*://[^/?]*(.*)$").unwrap();
 }
 
 /// Strips the given prefix from the front of s if s starts with that prefix.
 /// If the prefix does not match panic - only strings known to start with the prefix should be
 /// passed to this function.
 pub fn strip_prefix<'a>(s: &'a str, expected_prefix: &str) -> &'a str {
-    if s.starts_with(expected_prefix) {
-        return &s[expected_prefix.len()..]
+    if let Some(suffix) = s.strip_prefix(expected_prefix) {
+        return suffix
     }
     panic!("Expected {} to start with {}", s, expected_prefix)
 }
 
-pub fn strip_url_prefix(url: &str, expected_prefix: &str) -> UrlParts {
-    let suffix = strip_prefix(url, expected_prefix);
-    UrlParts::new(if suffix.is_empty() { "/" } else { suffix })
+/// Strips the given prefix from the front of url's path (see `strip_prefix`), keeping its query.
+/// The remainder is treated as an absolute path even if the prefix was the whole path.
+pub fn strip_url_prefix(url: &UrlParts, expected_prefix: &str) -> UrlParts {
+    let suffix = strip_prefix(&url.path, expected_prefix);
+    UrlParts {
+        path: if suffix.starts_with('/') { suffix.into() } else { format!("/{}", suffix) },
+        path_components: suffix.split('/').filter(|s| !s.is_empty()).map(percent_decode).collect(),
+        query: url.query.clone(),
+        _private: (),
+    }
+}
+
+/// Decodes `%XX` escapes as described in RFC 3986. Malformed escapes are left as-is, and any
+/// invalid UTF-8 in the result is replaced with U+FFFD.
+pub fn percent_decode(s: &str) -> String {
+    fn hex_value(b: u8) -> Option<u8> {
+        (b as char).to_digit(16).map(|d| d as u8)
+    }
+
+    let bytes = s.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        if bytes[i] == b'%' && i + 2 < bytes.len() {
+            if let (Some(hi), Some(lo)) = (hex_value(bytes[i + 1]), hex_value(bytes[i + 2])) {
+                decoded.push(hi << 4 | lo);
+                i += 3;
+                continue;
+            }
+        }
+        decoded.push(bytes[i]);
+        i += 1;
+    }
+    String::from_utf8_lossy(&decoded).into_owned()
+}
+
+/// Encodes `s` for use as a single path segment, escaping everything except RFC 3986 unreserved
+/// characters; the inverse of `percent_decode`.
+pub fn percent_encode(s: &str) -> String {
+    let mut encoded = String::with_capacity(s.len());
+    for &b in s.as_bytes() {
+        match b {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' =>
+                encoded.push(b as char),
+            _ => encoded.push_str(&format!("%{:02X}", b)),
+        }
+    }
+    encoded
+}
+
+/// Decodes a query string key or value, which (per `application/x-www-form-urlencoded`) may also
+/// encode spaces as `+`.
+fn query_decode(s: &str) -> String {
+    percent_decode(&s.replace('+', " "))
+}
+
+/// The decoded parameters of a URL's query string, in the order they appeared. Unlike a map, keys
+/// may repeat, e.g. `?tag=a&tag=b`.
+#[derive(Clone, Default, PartialEq, Eq)]
+pub struct Query {
+    pairs: Vec<(String, String)>,
+}
+
+impl Query {
+    /// Parses a query string (without the leading `?`). Keys without a value, e.g. `?debug`, are
+    /// given an empty value.
+    pub fn parse(query: &str) -> Query {
+        let pairs = query.split('&')
+            .filter(|pair| !pair.is_empty())
+            .map(|pair| {
+                let (key, value) = pair.split_once('=').unwrap_or((pair, ""));
+                (query_decode(key), query_decode(value))
+            })
+            .collect();
+        Query { pairs }
+    }
+
+    /// The first value of the given key, if present.
+    pub fn get(&self, key: &str) -> Option<&str> {
+        self.iter().find(|&(k, _)| k == key).map(|(_, v)| v)
+    }
+
+    /// Every value of the given key, in order.
+    #[allow(dead_code)]
+    pub fn get_all(&self, key: &str) -> Vec<&str> {
+        self.iter().filter(|&(k, _)| k == key).map(|(_, v)| v).collect()
+    }
+
+    /// The first value of the given key parsed as a `T`, or `None` if the key isn't present. A
+    /// value that doesn't parse is the client's mistake, and is reported as a `BadRequest`.
+    #[allow(dead_code)]
+    pub fn get_parsed<T: FromStr>(&self, key: &str) -> Result<Option<T>, RivetError>
+            where T::Err: fmt::Display {
+        match self.get(key) {
+            Some(value) => value.parse().map(Some).map_err(|e| RivetError::BadRequest(
+                format!("Invalid value {:?} for query parameter {}: {}", value, key, e))),
+            None => Ok(None),
+        }
+    }
+
+    #[allow(dead_code)]
+    pub fn contains_key(&self, key: &str) -> bool { self.get(key).is_some() }
+
+    /// The number of parameters, counting each occurrence of a repeated key.
+    #[allow(dead_code)]
+    pub fn len(&self) -> usize { self.pairs.len() }
+
+    #[allow(dead_code)]
+    pub fn is_empty(&self) -> bool { self.pairs.is_empty() }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&str, &str)> {
+        self.pairs.iter().map(|(k, v)| (k.as_str(), v.as_str()))
+    }
+}
+
+impl fmt::Debug for Query {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_map().entries(self.iter()).finish()
+    }
 }
 
 #[derive(Debug, Clone)]
 pub struct UrlParts {
+    /// The normalized path, still percent-encoded; `*` for an asterisk-form request target
     pub path: String,
+    /// The decoded, non-empty segments of the path
     pub path_components: Vec<String>,
-    pub query: HashMap<String, String>,
+    pub query: Query,
     _private: () // https://github.com/rust-unofficial/patterns/blob/master/idioms/priv-extend.md
 }
 
 impl UrlParts {
+    /// Parses a URL, panicking if it's invalid. Prefer `parse()` for URLs supplied by clients.
+    #[allow(dead_code)]
     pub fn new(url: &str) -> UrlParts {
-        if ! url.starts_with('/') { panic!("Invalid URL - must start with a /, was {}", url); }
-        let url_split = URL_SPLIT.captures(url).unwrap();
-        let path = &url_split[1];
-        let query_str = url_split.get(2);
+        UrlParts::parse(url).unwrap_or_else(|e| panic!("{}", e))
+    }
 
-        let url_components: Vec<_> = PATH_SEGMENTS.captures_iter(path)
-            .map(|cap| cap.get(1).unwrap().as_str().into())
-            .collect();
+    /// Parses an HTTP request target (RFC 7230, section 5.3). Absolute-form targets such as
+    /// `http://host/path` are reduced to their path and query, the fragment (if any) is dropped,
+    /// and the asterisk-form target `*` is represented by the path `*`.
+    ///
+    /// The path is normalized - duplicate slashes are collapsed and `.` and `..` segments are
+    /// resolved (`..` never climbs above the root) - so responders can rely on
+    /// `path_components()` not escaping the URL they were mounted at.
+    pub fn parse(target: &str) -> Result<UrlParts, RivetError> {
+        if let Some(c) = target.chars().find(|c| c.is_control() || c.is_whitespace()) {
+            return Err(RivetError::BadRequest(
+                format!("Invalid URL {:?} - must not contain {:?}", target, c)));
+        }
+        let target = target.split('#').next().unwrap_or("");
+        if target == "*" {
+            return Ok(UrlParts {
+                path: "*".into(), path_components: vec![], query: Query::default(), _private: () });
+        }
 
-        let url_query = match query_str {
-            Some(query) => {
-                query.as_str().split('&')
-                    .map(|q| QUERY_SEGMENT.captures(q).unwrap())
-                    .map(|cap|
-                        (cap.get(1).unwrap().as_str().into(),
-                         cap.get(2).map(|m| m.as_str()).unwrap_or("").into()))
-                    .collect()
-            },
-            None => HashMap::new()
+        let origin = match ABSOLUTE_FORM.captures(target) {
+            Some(caps) if !caps[1].starts_with('/') => format!("/{}", &caps[1]),
+            Some(caps) => caps[1].to_string(),
+            None => target.to_string(),
         };
-        UrlParts {path: path.into(), path_components: url_components, query: url_query, _private:()}
+        if !origin.starts_with('/') {
+            return Err(RivetError::BadRequest(
+                format!("Invalid URL {:?} - must start with a /", target)));
+        }
+        let url_split = URL_SPLIT.captures(&origin).unwrap();
+        let query_str = url_split.get(2);
+
+        // Segments are split before decoding, so an encoded slash (%2F) stays inside its segment,
+        // but an encoded dot segment (%2E%2E) is still resolved
+        let mut segments: Vec<(&str, String)> = Vec::new();
+        let mut trailing_slash = false;
+        for segment in url_split[1].split('/').skip(1) {
+            let decoded = percent_decode(segment);
+            trailing_slash = true;
+            match decoded.as_str() {
+                "" | "." => {},
+                ".." => { segments.pop(); },
+                _ => {
+                    segments.push((segment, decoded));
+                    trailing_slash = false;
+                },
+            }
+        }
+        let mut path = format!("/{}", segments.iter().map(|s| s.0).collect::<Vec<_>>().join("/"));
+        if trailing_slash && !segments.is_empty() {
+            path.push('/');
+        }
+        let url_components = segments.into_iter().map(|s| s.1).collect();
+
+        let url_query = query_str.map(|q| Query::parse(q.as_str())).unwrap_or_default();
+        Ok(UrlParts {path, path_components: url_components, query: url_query, _private:()})
     }
 
+    /// Whether this is the asterisk-form target `*`, used by `OPTIONS *` to refer to the server
+    /// as a whole rather than a particular resource.
+    pub fn is_asterisk(&self) -> bool { self.path == "*" }
+
     #[allow(dead_code)]
     pub fn path(&self) -> &str {
         &self.path
@@ -93,12 +556,13 @@ impl UrlParts {
 
     pub fn path_components(&self) -> &Vec<String> { &self.path_components }
 
-    pub fn query(&self) -> &HashMap<String, String> { &self.query }
+    pub fn query(&self) -> &Query { &self.query }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use responders::testing::body;
 
     #[test]
     fn strip_prefix_basic() {
@@ -135,10 +599,196 @@ mod tests {
         UrlParts::new("foo/bar/baz");
     }
 
+    #[test]
+    fn urlparts_decoding() {
+        let parts = UrlParts::new("/caf%C3%A9/a%2Fb/100%?name=a%20b+c&sum=1%2B1&%zz");
+        assert_eq!(parts.path, "/caf%C3%A9/a%2Fb/100%");
+        assert_eq!(parts.path_components, vec!["café", "a/b", "100%"]);
+        assert_eq!(parts.query.get("name"), Some("a b c"));
+        assert_eq!(parts.query.get("sum"), Some("1+1"));
+        assert_eq!(parts.query.get("%zz"), Some(""));
+        assert_eq!(percent_decode("%e9t%C3"), "\u{FFFD}t\u{FFFD}");
+    }
+
+    #[test]
+    fn percent_encoding() {
+        assert_eq!(percent_encode("a b/café~"), "a%20b%2Fcaf%C3%A9~");
+        assert_eq!(percent_decode(&percent_encode("100% ?&#")), "100% ?&#");
+    }
+
+    #[test]
+    fn query_repeated_keys() {
+        let query = Query::parse("tag=a&tag=b&&page=2&page=x");
+        assert_eq!(query.len(), 4);
+        assert_eq!(query.get("tag"), Some("a"));
+        assert_eq!(query.get_all("tag"), vec!["a", "b"]);
+        assert_eq!(query.get_all("missing"), Vec::<&str>::new());
+        assert_eq!(format!("{:?}", query), r#"{"tag": "a", "tag": "b", "page": "2", "page": "x"}"#);
+    }
+
+    #[test]
+    fn query_parsed() {
+        let query = Query::parse("page=2&size=big");
+        assert_eq!(query.get_parsed::<u32>("page"), Ok(Some(2)));
+        assert_eq!(query.get_parsed::<u32>("missing"), Ok(None));
+        assert_eq!(query.get_parsed::<u32>("size").unwrap_err().status_code(), 400);
+    }
+
     #[test]
     fn split_url_basic() {
-        assert_eq!(strip_url_prefix("/foo", "/foo").path, "/");
-        assert_eq!(strip_url_prefix("/foo/", "/foo").path, "/");
-        assert_eq!(strip_url_prefix("/foo/bar", "/foo").path, "/bar");
+        let strip = |url, prefix| strip_url_prefix(&UrlParts::new(url), prefix);
+        assert_eq!(strip("/foo", "/foo").path, "/");
+        assert_eq!(strip("/foo/", "/foo").path, "/");
+        assert_eq!(strip("/foo/bar", "/foo").path, "/bar");
+        assert_eq!(strip("/foo/a%2Fb", "/foo").path_components, vec!["a/b"]);
+        assert_eq!(strip("/foo?bar", "/foo").path, "/");
+        assert_eq!(strip("/foo?bar", "/foo").query.len(), 1);
+    }
+
+    #[test]
+    fn parse_request_targets() {
+        let parts = UrlParts::parse("http://example.com:8000/foo/bar?baz=1#frag").unwrap();
+        assert_eq!(parts.path, "/foo/bar");
+        assert_eq!(parts.query.get("baz"), Some("1"));
+        assert_eq!(UrlParts::parse("https://example.com").unwrap().path, "/");
+        assert_eq!(UrlParts::parse("http://example.com?q").unwrap().query.len(), 1);
+        assert!(UrlParts::parse("*").unwrap().is_asterisk());
+        assert_eq!(UrlParts::parse("/foo#bar?baz").unwrap().query.len(), 0);
+    }
+
+    #[test]
+    fn parse_normalizes() {
+        let path = |url| UrlParts::parse(url).unwrap().path;
+        assert_eq!(path("//foo///bar/"), "/foo/bar/");
+        assert_eq!(path("/foo/./bar/."), "/foo/bar/");
+        assert_eq!(path("/foo/../bar"), "/bar");
+        assert_eq!(path("/../../etc/passwd"), "/etc/passwd");
+        assert_eq!(path("/foo/%2e%2E/bar"), "/bar");
+        assert_eq!(path("/foo/.."), "/");
+        assert_eq!(UrlParts::parse("/a/../b/").unwrap().path_components, vec!["b"]);
+    }
+
+    #[test]
+    fn parse_rejects_malformed() {
+        let error = |url| UrlParts::parse(url).unwrap_err().status_code();
+        assert_eq!(error("foo/bar"), 400);
+        assert_eq!(error(""), 400);
+        assert_eq!(error("example.com:443"), 400);
+        assert_eq!(error("/foo bar"), 400);
+        assert_eq!(error("/foo\nbar"), 400);
+    }
+
+    fn header_values(response: &tiny_http::ResponseBox, name: &'static str) -> Vec<String> {
+        response.headers().iter().filter(|h| h.field.equiv(name))
+            .map(|h| h.value.to_string()).collect()
+    }
+
+    #[test]
+    fn response_text() {
+        let response = ResponseBuilder::ok().text("hello").build().unwrap();
+        assert_eq!(response.status_code().0, 200);
+        assert_eq!(response.data_length(), Some(5));
+        assert_eq!(header_values(&response, "Content-Type"), vec!["text/plain; charset=UTF-8"]);
+        assert_eq!(body(response), "hello");
+
+        let response = ResponseBuilder::new(418).html("<p>hi</p>").content_type("text/x-custom")
+            .build().unwrap();
+        assert_eq!(response.status_code().0, 418);
+        assert_eq!(header_values(&response, "Content-Type"), vec!["text/x-custom"]);
+    }
+
+    #[test]
+    fn response_json() {
+        let response = ResponseBuilder::ok().json(&vec![1, 2]).build().unwrap();
+        assert_eq!(header_values(&response, "Content-Type"), vec!["application/json"]);
+        assert_eq!(body(response), "[1,2]");
+    }
+
+    #[test]
+    fn response_redirect_and_cookies() {
+        let response = ResponseBuilder::redirect(RedirectStatus::TemporaryRedirect, "/elsewhere")
+            .cookie(Cookie::new("a", "1"))
+            .cookie(Cookie::new("b", "2").path("/").max_age(60).http_only().secure()
+                .same_site(SameSite::Lax))
+            .build().unwrap();
+        assert_eq!(response.status_code().0, 307);
+        assert_eq!(header_values(&response, "Location"), vec!["/elsewhere"]);
+        assert_eq!(header_values(&response, "Set-Cookie"),
+                   vec!["a=1", "b=2; Path=/; Max-Age=60; HttpOnly; Secure; SameSite=Lax"]);
+    }
+
+    #[test]
+    fn invalid_cookies() {
+        let invalid = vec![
+            Cookie::new("", "1"),
+            Cookie::new("a b", "1"),
+            Cookie::new("a=b", "1"),
+            Cookie::new("a", "1; Domain=evil.example"),
+            Cookie::new("a", "1\r\nLocation: /evil"),
+            Cookie::new("a", "caf\u{e9}"),
+            Cookie::new("a", "1").path("/; HttpOnly"),
+        ];
+        for cookie in invalid {
+            match ResponseBuilder::ok().cookie(cookie.clone()).build() {
+                Err(RivetError::Internal(_)) => {},
+                _ => panic!("{:?} should be rejected", cookie),
+            }
+        }
+        let response = ResponseBuilder::ok()
+            .cookie(Cookie::new("a", "x%3B%20y").path("/my docs")).build().unwrap();
+        assert_eq!(header_values(&response, "Set-Cookie"), vec!["a=x%3B%20y; Path=/my docs"]);
+    }
+
+    #[test]
+    fn response_stream() {
+        let response = ResponseBuilder::ok().stream(&b"streamed"[..], None).build().unwrap();
+        assert_eq!(response.data_length(), None);
+        assert_eq!(body(response), "streamed");
+
+        let file = fs::File::open(file!()).unwrap();
+        let length = file.metadata().unwrap().len() as usize;
+        let response = ResponseBuilder::ok().file(file).build().unwrap();
+        assert_eq!(response.data_length(), Some(length));
+    }
+
+    #[test]
+    fn typed_headers() {
+        let modified = SystemTime::UNIX_EPOCH + Duration::from_secs(784111777);
+        let response = ResponseBuilder::ok()
+            .cache_control(CacheControl::NoStore)
+            .cache_control(CacheControl::Private(Duration::from_secs(60)))
+            .etag("W/\"v1\"")
+            .last_modified(modified)
+            .location("/elsewhere")
+            .build().unwrap();
+        assert_eq!(header_values(&response, "Cache-Control"), vec!["private, max-age=60"]);
+        assert_eq!(header_values(&response, "ETag"), vec!["W/\"v1\""]);
+        assert_eq!(header_values(&response, "Last-Modified"),
+                   vec!["Sun, 06 Nov 1994 08:49:37 GMT"]);
+        assert_eq!(header_values(&response, "Location"), vec!["/elsewhere"]);
+        for etag in &["v1", "\"", "\"a\"b\"", "\"a b\""] {
+            assert!(ResponseBuilder::ok().etag(etag).build().is_err(), "{} is invalid", etag);
+        }
+    }
+
+    #[test]
+    fn no_body() {
+        let response = ResponseBuilder::no_content().json(&vec![1]).build().unwrap();
+        assert_eq!(response.data_length(), Some(0));
+        assert!(header_values(&response, "Content-Type").is_empty());
+        assert_eq!(body(response), "");
+
+        let response = ResponseBuilder::new(304).text("unchanged").etag("\"v1\"").build().unwrap();
+        assert_eq!(response.data_length(), Some(9));
+        assert!(header_values(&response, "Content-Type").is_empty());
+        assert_eq!(header_values(&response, "ETag"), vec!["\"v1\""]);
+        assert_eq!(body(response), "");
+    }
+
+    #[test]
+    fn response_invalid_header() {
+        let result = ResponseBuilder::no_content().header("X-Bad", "caf\u{e9}").build();
+        assert_eq!(result.err().map(|e| e.status_code()), Some(500));
+        assert_eq!(ResponseBuilder::no_content().build().unwrap().status_code().0, 204);
     }
 }
@@ -22,6 +22,7 @@
 
 PORT=8000
 HOST='localhost'
+ADMIN_TOKEN="test-$$"
 
 PATHS=(
   '/'
@@ -35,6 +36,8 @@ PATHS=(
   '/traits/bar?baz'
   '/traits_macro/all/bar?baz'
   '/factory/both/foo?bar'
+  '/factory/health'
+  '/codegen/users/7?verbose'
 )
 
 expect() {
@@ -58,7 +61,7 @@ make_requests() {
     urls+=("http://${HOST}:${PORT}${url}")
   done
   # shut down the server
-  urls+=("http://${HOST}:${PORT}/quit")
+  urls+=("http://${HOST}:${PORT}/quit?token=${ADMIN_TOKEN}")
 
   wget --quiet --spider "${urls[@]}"
 }
@@ -69,4 +72,4 @@ make_requests &
 trap 'kill %1' EXIT
 
 # Build and bring up server, make_requests will send requests once it's up
-RUST_BACKTRACE=1 cargo run
+RUST_BACKTRACE=1 cargo run -- --port "$PORT" --admin-token "$ADMIN_TOKEN"